@@ -0,0 +1,124 @@
+use crate::error::{ImplinkError, Result};
+use crate::paths::expand;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_to_string, symlink_metadata, write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Suffix used for backups when none is given.
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".implink-bak";
+
+/// Current time in seconds since the Unix epoch.
+pub(crate) fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Where destinations replaced with `force` are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupMode {
+    /// Rename the destination to `<name><suffix>.<timestamp>` next to itself
+    Suffix(String),
+    /// Move the destination to `<dir>/<name>.<timestamp>`
+    Dir(PathBuf),
+}
+
+impl Default for BackupMode {
+    fn default() -> Self {
+        BackupMode::Suffix(DEFAULT_BACKUP_SUFFIX.to_string())
+    }
+}
+
+impl BackupMode {
+    /// Picks a path for the backup of `path` which doesn't exist yet.
+    pub(crate) fn backup_path(&self, path: &Path) -> PathBuf {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let timestamp = timestamp();
+        let base = match self {
+            BackupMode::Suffix(suffix) => {
+                path.with_file_name(format!("{}{}.{}", name, suffix, timestamp))
+            }
+            BackupMode::Dir(dir) => dir.join(format!("{}.{}", name, timestamp)),
+        };
+        let mut candidate = base.clone();
+        let mut index = 1;
+        while symlink_metadata(&candidate).is_ok() {
+            candidate = PathBuf::from(format!("{}.{}", base.display(), index));
+            index += 1;
+        }
+        candidate
+    }
+}
+
+/// A destination which has been moved aside instead of being removed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackupEntry {
+    /// Where the file or directory was
+    pub original: PathBuf,
+    /// Where it is now
+    pub backup: PathBuf,
+    /// When the backup was made, in seconds since the Unix epoch
+    pub created: u64,
+}
+
+/// Record of every backup made, so they can be put back with `restore-backup`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackupManifest {
+    pub backups: Vec<BackupEntry>,
+}
+
+impl BackupManifest {
+    /// Default location of the manifest, `$XDG_STATE_HOME/implink/backups.json`.
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(PathBuf::from(expand(
+            "${XDG_STATE_HOME}/implink/backups.json",
+        )?))
+    }
+
+    /// Reads the manifest from disk, or returns an empty one if it doesn't exist yet.
+    pub fn load_or_default(path: &Path) -> Result<BackupManifest> {
+        let json = match read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(BackupManifest::default())
+            }
+            Err(e) => {
+                return Err(ImplinkError::ManifestIo {
+                    path: path.to_path_buf(),
+                    source: e,
+                })
+            }
+        };
+        serde_json::from_str(&json).map_err(|e| ImplinkError::ManifestParse {
+            path: path.to_path_buf(),
+            source: e,
+        })
+    }
+
+    /// Writes the manifest to disk, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let io_error = |e| ImplinkError::ManifestIo {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).map_err(io_error)?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| ImplinkError::ManifestParse {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        write(path, json).map_err(io_error)
+    }
+
+    /// The most recent backup of `original`, with its index.
+    pub fn latest(&self, original: &Path) -> Option<(usize, &BackupEntry)> {
+        self.backups
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, entry)| entry.original == original)
+    }
+}
@@ -0,0 +1,312 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Result type used throughout implink.
+pub type Result<T> = std::result::Result<T, ImplinkError>;
+
+/// Everything that can go wrong while linking, moving or handling mapping files.
+///
+/// Each variant keeps the path(s) involved and, where there is one, the underlying
+/// I/O error. [`ImplinkError::exit_code`] maps the variant to the CLI exit status.
+#[derive(Debug)]
+pub enum ImplinkError {
+    /// A path could not be resolved to an absolute path
+    InvalidPath { path: PathBuf, source: io::Error },
+    /// The source file or directory does not exist
+    SourceMissing { path: PathBuf },
+    /// The destination already exists and `force` is not set
+    DestinationExists { path: PathBuf },
+    /// The destination directory is not empty and `force` is not set
+    DestinationNotEmpty { path: PathBuf },
+    /// The path was expected to be a symlink or junction
+    NotALink { path: PathBuf },
+    /// The link points somewhere other than expected
+    LinkMismatch {
+        path: PathBuf,
+        expected: PathBuf,
+        actual: PathBuf,
+    },
+    /// Checking, reading or creating a path failed
+    Io { path: PathBuf, source: io::Error },
+    /// Removing an existing destination failed
+    RemoveFailed { path: PathBuf, source: io::Error },
+    /// Moving the source to the destination failed
+    MoveFailed {
+        src: PathBuf,
+        dst: PathBuf,
+        source: io::Error,
+    },
+    /// Creating the link itself failed
+    LinkFailed {
+        src: PathBuf,
+        dst: PathBuf,
+        source: io::Error,
+    },
+    /// A hard link was requested between different filesystems
+    CrossDevice { src: PathBuf, dst: PathBuf },
+    /// The mapping file could not be read or written
+    MappingIo { path: PathBuf, source: io::Error },
+    /// A path uses an environment variable which is not set
+    UndefinedVariable { name: String, path: String },
+    /// A glob pattern in a mapping file is invalid
+    InvalidPattern {
+        pattern: String,
+        source: glob::PatternError,
+    },
+    /// An ignore file or an ignore pattern is invalid
+    InvalidIgnore { source: ignore::Error },
+    /// There is no backup of the path in the backup manifest
+    NoBackup { path: PathBuf },
+    /// The mapping file has no entry for the path
+    NoEntry { path: PathBuf, file: PathBuf },
+    /// The backup manifest could not be read or written
+    ManifestIo { path: PathBuf, source: io::Error },
+    /// The backup manifest is not valid JSON or does not match the expected format
+    ManifestParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// The mapping file is not valid JSON or does not match the expected format
+    MappingParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// Stowing `package` would replace the `conflicts` in the target directory, nothing
+    /// has been linked
+    StowConflict {
+        package: PathBuf,
+        conflicts: Vec<PathBuf>,
+    },
+    /// Checking whether `src` can be moved to `dst` found problems, nothing has been moved
+    PreflightFailed {
+        src: PathBuf,
+        dst: PathBuf,
+        problems: Vec<String>,
+    },
+    /// Moving was stopped through [`crate::Linker::interrupt`], after moving `files`
+    /// files totalling `bytes` bytes
+    Interrupted {
+        src: PathBuf,
+        dst: PathBuf,
+        files: u64,
+        bytes: u64,
+    },
+}
+
+impl ImplinkError {
+    /// Process exit code for this error.
+    ///
+    /// | Code | Meaning                                                   |
+    /// |------|-----------------------------------------------------------|
+    /// | 1    | Other I/O error                                           |
+    /// | 2    | Invalid command line usage                                |
+    /// | 3    | Source, backup or mapping file entry does not exist       |
+    /// | 4    | Destination exists or is not empty, or stow conflicts     |
+    /// | 5    | Removing the existing destination or a link failed        |
+    /// | 6    | Moving the source failed                                  |
+    /// | 7    | Creating the link failed                                  |
+    /// | 8    | Mapping file or backup manifest could not be read, parsed |
+    /// |      | or written, a path uses an unset environment variable, or |
+    /// |      | a glob or ignore pattern is invalid                       |
+    /// | 10   | Path is not a link, or not a link to the expected target  |
+    /// | 11   | Preflight checks before moving failed                     |
+    /// | 130  | Interrupted while moving                                  |
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ImplinkError::InvalidPath { .. } | ImplinkError::Io { .. } => 1,
+            ImplinkError::SourceMissing { .. }
+            | ImplinkError::NoBackup { .. }
+            | ImplinkError::NoEntry { .. } => 3,
+            ImplinkError::DestinationExists { .. }
+            | ImplinkError::DestinationNotEmpty { .. }
+            | ImplinkError::StowConflict { .. } => 4,
+            ImplinkError::RemoveFailed { .. } => 5,
+            ImplinkError::MoveFailed { .. } => 6,
+            ImplinkError::LinkFailed { .. } | ImplinkError::CrossDevice { .. } => 7,
+            ImplinkError::MappingIo { .. }
+            | ImplinkError::MappingParse { .. }
+            | ImplinkError::UndefinedVariable { .. }
+            | ImplinkError::InvalidPattern { .. }
+            | ImplinkError::InvalidIgnore { .. }
+            | ImplinkError::ManifestIo { .. }
+            | ImplinkError::ManifestParse { .. } => 8,
+            ImplinkError::NotALink { .. } | ImplinkError::LinkMismatch { .. } => 10,
+            ImplinkError::PreflightFailed { .. } => 11,
+            ImplinkError::Interrupted { .. } => 130,
+        }
+    }
+}
+
+impl fmt::Display for ImplinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImplinkError::InvalidPath { path, source } => {
+                write!(f, "Failed to resolve path '{}': {}", path.display(), source)
+            }
+            ImplinkError::SourceMissing { path } => write!(
+                f,
+                "Source file or directory '{}' does not exist",
+                path.display()
+            ),
+            ImplinkError::DestinationExists { path } => write!(
+                f,
+                "Destination file or directory '{}' already exists",
+                path.display()
+            ),
+            ImplinkError::DestinationNotEmpty { path } => {
+                write!(f, "Destination directory '{}' is not empty", path.display())
+            }
+            ImplinkError::NotALink { path } => {
+                write!(f, "'{}' is not a symlink or junction", path.display())
+            }
+            ImplinkError::LinkMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "'{}' points to '{}' instead of '{}'",
+                path.display(),
+                actual.display(),
+                expected.display()
+            ),
+            ImplinkError::Io { path, source } => write!(f, "'{}': {}", path.display(), source),
+            ImplinkError::RemoveFailed { path, source } => write!(
+                f,
+                "Failed to remove destination file or directory '{}': {}",
+                path.display(),
+                source
+            ),
+            ImplinkError::MoveFailed { src, dst, source } => write!(
+                f,
+                "Failed to move '{}' to '{}': {}",
+                src.display(),
+                dst.display(),
+                source
+            ),
+            ImplinkError::LinkFailed { src, dst, source } => write!(
+                f,
+                "Failed to create link '{}' to '{}': {}",
+                dst.display(),
+                src.display(),
+                source
+            ),
+            ImplinkError::CrossDevice { src, dst } => write!(
+                f,
+                "Can't hard link '{}' to '{}', they are on different filesystems",
+                dst.display(),
+                src.display()
+            ),
+            ImplinkError::MappingIo { path, source } => {
+                write!(
+                    f,
+                    "Failed to access mapping file '{}': {}",
+                    path.display(),
+                    source
+                )
+            }
+            ImplinkError::UndefinedVariable { name, path } => write!(
+                f,
+                "Environment variable '{}' used in '{}' is not set",
+                name, path
+            ),
+            ImplinkError::InvalidPattern { pattern, source } => {
+                write!(f, "Invalid pattern '{}': {}", pattern, source)
+            }
+            ImplinkError::InvalidIgnore { source } => {
+                write!(f, "Invalid ignore pattern: {}", source)
+            }
+            ImplinkError::NoBackup { path } => {
+                write!(f, "There is no backup of '{}'", path.display())
+            }
+            ImplinkError::NoEntry { path, file } => write!(
+                f,
+                "There is no entry for '{}' in mapping file '{}'",
+                path.display(),
+                file.display()
+            ),
+            ImplinkError::ManifestIo { path, source } => write!(
+                f,
+                "Failed to access backup manifest '{}': {}",
+                path.display(),
+                source
+            ),
+            ImplinkError::ManifestParse { path, source } => write!(
+                f,
+                "Failed to parse backup manifest '{}': {}",
+                path.display(),
+                source
+            ),
+            ImplinkError::MappingParse { path, source } => {
+                write!(
+                    f,
+                    "Failed to parse mapping file '{}': {}",
+                    path.display(),
+                    source
+                )
+            }
+            ImplinkError::StowConflict { package, conflicts } => write!(
+                f,
+                "Can't stow '{}', these already exist: {}",
+                package.display(),
+                conflicts
+                    .iter()
+                    .map(|path| format!("'{}'", path.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ImplinkError::PreflightFailed { src, dst, problems } => write!(
+                f,
+                "Can't move '{}' to '{}': {}",
+                src.display(),
+                dst.display(),
+                problems.join("; ")
+            ),
+            ImplinkError::Interrupted {
+                src,
+                dst,
+                files,
+                bytes,
+            } => write!(
+                f,
+                "Interrupted while moving '{}' to '{}' after moving {} files ({} bytes), \
+                 the rest is still at the source",
+                src.display(),
+                dst.display(),
+                files,
+                bytes
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImplinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImplinkError::InvalidPath { source, .. }
+            | ImplinkError::Io { source, .. }
+            | ImplinkError::RemoveFailed { source, .. }
+            | ImplinkError::MoveFailed { source, .. }
+            | ImplinkError::LinkFailed { source, .. }
+            | ImplinkError::MappingIo { source, .. }
+            | ImplinkError::ManifestIo { source, .. } => Some(source),
+            ImplinkError::MappingParse { source, .. }
+            | ImplinkError::ManifestParse { source, .. } => Some(source),
+            ImplinkError::InvalidPattern { source, .. } => Some(source),
+            ImplinkError::InvalidIgnore { source } => Some(source),
+            ImplinkError::SourceMissing { .. }
+            | ImplinkError::DestinationExists { .. }
+            | ImplinkError::DestinationNotEmpty { .. }
+            | ImplinkError::UndefinedVariable { .. }
+            | ImplinkError::NotALink { .. }
+            | ImplinkError::LinkMismatch { .. }
+            | ImplinkError::NoBackup { .. }
+            | ImplinkError::NoEntry { .. }
+            | ImplinkError::CrossDevice { .. }
+            | ImplinkError::StowConflict { .. }
+            | ImplinkError::PreflightFailed { .. }
+            | ImplinkError::Interrupted { .. } => None,
+        }
+    }
+}
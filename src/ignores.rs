@@ -0,0 +1,146 @@
+use crate::error::{ImplinkError, Result};
+use ::ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::fs::{read_dir, symlink_metadata};
+use std::path::{Path, PathBuf};
+
+/// Name of the files listing what to leave out of a tree, in gitignore syntax.
+pub(crate) const IGNORE_FILE: &str = ".implinkignore";
+
+/// Whether `path` is an ignore file, which trees are linked without.
+pub(crate) fn is_ignore_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == IGNORE_FILE)
+}
+
+/// Which entries of a tree are left alone, from the [`IGNORE_FILE`]s in it and extra
+/// patterns given for its root.
+///
+/// Every directory, the root included, is entered with [`Ignore::child`] before its
+/// entries are checked, so the rules of an ignore file only apply below its directory.
+/// The default ignores nothing and doesn't read any ignore file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Ignore {
+    /// Rules from the root down to the current directory
+    matchers: Vec<Gitignore>,
+    read_files: bool,
+}
+
+fn invalid(e: ::ignore::Error) -> ImplinkError {
+    ImplinkError::InvalidIgnore { source: e }
+}
+
+impl Ignore {
+    /// Rules for the tree at `root`, `patterns` and the ignore files in it.
+    pub(crate) fn new(root: &Path, patterns: &[String]) -> Result<Ignore> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in patterns {
+            builder.add_line(None, pattern).map_err(invalid)?;
+        }
+        Ok(Ignore {
+            matchers: vec![builder.build().map_err(invalid)?],
+            read_files: true,
+        })
+    }
+
+    /// Rules for the entries of `dir`, adding its ignore file if it has one.
+    pub(crate) fn child(&self, dir: &Path) -> Result<Ignore> {
+        let file = dir.join(IGNORE_FILE);
+        if !self.read_files || !file.is_file() {
+            return Ok(self.clone());
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&file) {
+            return Err(invalid(e));
+        }
+        let mut child = self.clone();
+        child.matchers.push(builder.build().map_err(invalid)?);
+        Ok(child)
+    }
+
+    /// Whether `path` is ignored, checked on the rules of its parent directory. The deepest ignore
+    /// file with a matching rule decides, `!pattern` rules take entries back in.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for matcher in self.matchers.iter().rev() {
+            let matched = matcher.matched(path, is_dir);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+        }
+        false
+    }
+
+    /// Whether anything in the tree at `path`, which isn't ignored itself, is ignored.
+    pub(crate) fn ignores_any(&self, path: &Path) -> Result<bool> {
+        if !symlink_metadata(path).is_ok_and(|m| m.is_dir()) {
+            return Ok(false);
+        }
+        let ignore = self.child(path)?;
+        for entry in entries(path)? {
+            let is_dir = symlink_metadata(&entry).is_ok_and(|m| m.is_dir());
+            if ignore.is_ignored(&entry, is_dir) || ignore.ignores_any(&entry)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether anything in the tree at `path` is ignored or is an ignore file, so a copy of
+    /// the tree left out what these rules ignore is missing something.
+    pub(crate) fn leaves_out_any(&self, path: &Path) -> Result<bool> {
+        if !symlink_metadata(path).is_ok_and(|m| m.is_dir()) {
+            return Ok(false);
+        }
+        let ignore = self.child(path)?;
+        for entry in entries(path)? {
+            let is_dir = symlink_metadata(&entry).is_ok_and(|m| m.is_dir());
+            if is_ignore_file(&entry)
+                || ignore.is_ignored(&entry, is_dir)
+                || ignore.leaves_out_any(&entry)?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The largest parts of the tree at `root` without anything ignored in them, relative
+    /// to `root`, or `None` if nothing in it is ignored.
+    pub(crate) fn kept_parts(&self, root: &Path) -> Result<Option<Vec<PathBuf>>> {
+        if !self.ignores_any(root)? {
+            return Ok(None);
+        }
+        let mut parts = Vec::new();
+        self.collect_kept(root, Path::new(""), &mut parts)?;
+        Ok(Some(parts))
+    }
+
+    fn collect_kept(&self, path: &Path, relative: &Path, parts: &mut Vec<PathBuf>) -> Result<()> {
+        if !self.ignores_any(path)? {
+            parts.push(relative.to_path_buf());
+            return Ok(());
+        }
+        let ignore = self.child(path)?;
+        for entry in entries(path)? {
+            let is_dir = symlink_metadata(&entry).is_ok_and(|m| m.is_dir());
+            if !ignore.is_ignored(&entry, is_dir) {
+                let name = entry.file_name().unwrap_or_default();
+                ignore.collect_kept(&entry, &relative.join(name), parts)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The entries of the directory `dir`, sorted.
+pub(crate) fn entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let io_error = |e| ImplinkError::Io {
+        path: dir.to_path_buf(),
+        source: e,
+    };
+    let mut paths = read_dir(dir)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(io_error)?;
+    paths.sort();
+    Ok(paths)
+}
@@ -0,0 +1,31 @@
+//! File symlinking made easy.
+//!
+//! The [`Linker`] builder creates symlinks (optionally moving the source first) and
+//! restores them from [`MappingFile`]s. Nothing in this crate prints; progress is
+//! reported through the [`Event`] callback set with [`Linker::on_event`].
+//! With [`Linker::dry_run`], every change is reported as an [`Action`] instead of
+//! being performed. [`status`] compares a mapping file to the filesystem without
+//! changing anything.
+
+mod backup;
+mod error;
+mod ignores;
+mod linker;
+mod mapping;
+mod mover;
+mod ops;
+mod paths;
+mod plan;
+mod preflight;
+mod report;
+mod status;
+mod stow;
+
+pub use backup::{BackupEntry, BackupManifest, BackupMode, DEFAULT_BACKUP_SUFFIX};
+pub use error::{ImplinkError, Result};
+pub use linker::{Event, Linker};
+pub use mapping::{LinkKind, Mapping, MappingFile};
+pub use mover::Preserve;
+pub use plan::Action;
+pub use report::{EntryReport, EntryStatus, Report};
+pub use status::{status, EntryState, LinkState};
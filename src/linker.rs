@@ -0,0 +1,1117 @@
+use crate::backup::{BackupEntry, BackupManifest, BackupMode};
+use crate::error::{ImplinkError, Result};
+use crate::mapping::{escape_glob, LinkKind, Mapping, MappingFile};
+use crate::mover::{self, Preserve};
+use crate::ops::Ops;
+use crate::paths::{escape, normalize, portable};
+use crate::plan::Action;
+use crate::report::{EntryReport, EntryStatus, Report};
+use crate::stow::{self, Step};
+use std::fs::read_link;
+use std::io::ErrorKind;
+use std::path::{absolute, Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Something that happened while linking, reported through [`Linker::on_event`].
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Progress of a file being moved by move-and-link
+    MoveProgress {
+        file: PathBuf,
+        dst: PathBuf,
+        percent: u64,
+    },
+    /// A file or directory has been moved
+    Moved { src: PathBuf, dst: PathBuf },
+    /// An interrupted move is being resumed, skipping what has already been moved
+    Resuming { src: PathBuf, dst: PathBuf },
+    /// Creating the symlink failed and the destination is being removed before retrying
+    Retrying { dst: PathBuf, reason: String },
+    /// A symlink has been created at `dst` pointing to `src`
+    Symlinked { src: PathBuf, dst: PathBuf },
+    /// `dst` has been hard linked to `src`, or mirrors it with hard links
+    HardLinked { src: PathBuf, dst: PathBuf },
+    /// An existing destination has been moved aside instead of being removed
+    BackedUp { src: PathBuf, backup: PathBuf },
+    /// A symlink or junction has been removed
+    Unlinked { dst: PathBuf },
+    /// A mapping file has been written
+    MappingWritten { file: PathBuf },
+    /// The hashes of the moved files have been written next to the destination
+    ChecksumsWritten { file: PathBuf },
+    /// A change has been undone after a failed atomic restore
+    RolledBack { path: PathBuf },
+    /// Undoing a change failed, `path` has been left as it is
+    RollbackFailed { path: PathBuf, reason: String },
+    /// An action which would be performed, only reported in a dry run
+    Planned(Action),
+}
+
+fn resolve(path: &Path) -> Result<PathBuf> {
+    absolute(path).map_err(|e| ImplinkError::InvalidPath {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+/// Expands the glob entries of a mapping file into one entry per link, along with whether
+/// the link goes inside the `dst` of its entry.
+///
+/// Entries which match nothing or can't be expanded are returned as their report instead.
+fn expand_all(
+    mappings: Vec<Mapping>,
+    base: &Path,
+) -> Vec<std::result::Result<(Mapping, bool), EntryReport>> {
+    let mut entries = Vec::new();
+    for mapping in mappings {
+        let inside = mapping.links_inside();
+        match mapping.expand(base) {
+            Ok(expanded) if expanded.is_empty() => entries.push(Err(EntryReport {
+                mapping,
+                status: EntryStatus::Skipped("nothing matches".to_string()),
+            })),
+            Ok(expanded) => entries.extend(expanded.into_iter().map(|e| (e, inside)).map(Ok)),
+            Err(e) => entries.push(Err(EntryReport {
+                mapping,
+                status: EntryStatus::Failed(e),
+            })),
+        }
+    }
+    entries
+}
+
+/// Asked whether adopting a destination may replace the existing source, see [`Linker::confirm_adopt`].
+type ConfirmAdopt<'a> = dyn Fn(&Path, &Path) -> bool + 'a;
+
+/// Creates symlinks, optionally moving the source first and recording the result in a mapping file.
+///
+/// ```no_run
+/// use implink::Linker;
+///
+/// let mapping = Linker::new()
+///     .force(true)
+///     .mapping_output("mappings.json")
+///     .link("dotfiles/.vimrc", "/home/user/.vimrc")
+///     .unwrap();
+/// println!("{} -> {}", mapping.dst, mapping.src);
+/// ```
+pub struct Linker<'a> {
+    force: bool,
+    junction: bool,
+    kind: LinkKind,
+    relative: bool,
+    move_and_link: bool,
+    dry_run: bool,
+    atomic: bool,
+    restore_data: bool,
+    prune: bool,
+    backup: Option<BackupMode>,
+    backup_manifest: Option<PathBuf>,
+    mapping_output: Option<PathBuf>,
+    portable: bool,
+    preserve: Preserve,
+    verify: bool,
+    checksum_manifest: bool,
+    interrupt: Option<Arc<AtomicBool>>,
+    skip_preflight: bool,
+    ignore: Vec<String>,
+    adopt: bool,
+    confirm_adopt: Box<ConfirmAdopt<'a>>,
+    on_event: Box<dyn Fn(Event) + 'a>,
+}
+
+impl Default for Linker<'_> {
+    fn default() -> Self {
+        Linker::new()
+    }
+}
+
+impl<'a> Linker<'a> {
+    pub fn new() -> Self {
+        Linker {
+            force: false,
+            junction: false,
+            kind: LinkKind::Symlink,
+            relative: false,
+            move_and_link: false,
+            dry_run: false,
+            atomic: false,
+            restore_data: false,
+            prune: false,
+            backup: None,
+            backup_manifest: None,
+            mapping_output: None,
+            portable: false,
+            preserve: Preserve::default(),
+            verify: false,
+            checksum_manifest: false,
+            interrupt: None,
+            skip_preflight: false,
+            ignore: Vec::new(),
+            adopt: false,
+            confirm_adopt: Box::new(|_, _| false),
+            on_event: Box::new(|_| {}),
+        }
+    }
+
+    /// Force overwrite of existing destination
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Use NTFS junction for directories on Windows
+    pub fn junction(mut self, junction: bool) -> Self {
+        self.junction = junction;
+        self
+    }
+
+    /// Create hard links instead of symlinks. Directories are mirrored with every file
+    /// hard linked, which only works on the same filesystem
+    pub fn kind(mut self, kind: LinkKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Store the path to the source relative to the directory of the link, so the link
+    /// keeps working when both are moved together. Ignored for junctions and hard links
+    pub fn relative(mut self, relative: bool) -> Self {
+        self.relative = relative;
+        self
+    }
+
+    /// When the destination is a real file or directory, move it to the source and link
+    /// it back instead of failing or replacing it. An existing source is only replaced
+    /// if [`Linker::confirm_adopt`] agrees, and backed up with [`Linker::backup`]
+    pub fn adopt(mut self, adopt: bool) -> Self {
+        self.adopt = adopt;
+        self
+    }
+
+    /// Set the callback asked whether adopting the destination (the second path) may
+    /// replace the existing source (the first path). Without one, adopting fails with
+    /// [`ImplinkError::DestinationExists`] when the source exists
+    pub fn confirm_adopt(mut self, callback: impl Fn(&Path, &Path) -> bool + 'a) -> Self {
+        self.confirm_adopt = Box::new(callback);
+        self
+    }
+
+    /// Move the source to the destination and create a symlink back
+    pub fn move_and_link(mut self, move_and_link: bool) -> Self {
+        self.move_and_link = move_and_link;
+        self
+    }
+
+    /// Only report what would be done as [`Event::Planned`], without touching the filesystem
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Make [`Linker::restore`] all-or-nothing: replaced destinations are kept aside
+    /// until every entry succeeded, and put back if one fails
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Move the data back to where the link was when unlinking
+    pub fn restore_data(mut self, restore_data: bool) -> Self {
+        self.restore_data = restore_data;
+        self
+    }
+
+    /// Back up destinations replaced with `force` instead of removing them
+    pub fn backup(mut self, backup: Option<BackupMode>) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Record backups in this file instead of `$XDG_STATE_HOME/implink/backups.json`
+    pub fn backup_manifest(mut self, file: impl Into<PathBuf>) -> Self {
+        self.backup_manifest = Some(file.into());
+        self
+    }
+
+    /// Remove parent directories which become empty when unlinking
+    pub fn prune(mut self, prune: bool) -> Self {
+        self.prune = prune;
+        self
+    }
+
+    /// Add the created link to a mapping file, replacing any entry with the same destination.
+    /// Unlinking removes the entry again
+    pub fn mapping_output(mut self, file: impl Into<PathBuf>) -> Self {
+        self.mapping_output = Some(file.into());
+        self
+    }
+
+    /// Write paths to the mapping file relative to its directory or to `~` where possible
+    pub fn portable(mut self, portable: bool) -> Self {
+        self.portable = portable;
+        self
+    }
+
+    /// Metadata to keep when move-and-link has to copy files to another filesystem
+    pub fn preserve(mut self, preserve: Preserve) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Hash every file copied to another filesystem and compare it to the source
+    /// before the source is deleted, failing with the source kept on a mismatch
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Write the BLAKE3 hashes of the moved files next to the destination of
+    /// move-and-link, as `<dst>.b3sum` in the format used by `b3sum`
+    pub fn checksum_manifest(mut self, checksum_manifest: bool) -> Self {
+        self.checksum_manifest = checksum_manifest;
+        self
+    }
+
+    /// Stop moving files as soon as `interrupt` is set, for example from a Ctrl-C handler.
+    ///
+    /// The file being copied is removed from the destination again, so every file is
+    /// either fully moved or still at the source, and the move fails with
+    /// [`ImplinkError::Interrupted`]. Moves of directories can be resumed later.
+    pub fn interrupt(mut self, interrupt: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Don't check that the source can be read and removed, and that the destination has
+    /// enough free space and inodes, before moving. Those checks fail with
+    /// [`ImplinkError::PreflightFailed`] before anything is touched
+    pub fn skip_preflight(mut self, skip_preflight: bool) -> Self {
+        self.skip_preflight = skip_preflight;
+        self
+    }
+
+    /// Extra patterns in gitignore syntax for entries to leave alone when moving or linking
+    /// trees, on top of the `.implinkignore` files in them. Moved directories with ignored
+    /// entries stay at the source and their other contents are linked one by one
+    pub fn ignore(mut self, patterns: Vec<String>) -> Self {
+        self.ignore = patterns;
+        self
+    }
+
+    /// Set the callback which receives progress events
+    pub fn on_event(mut self, callback: impl Fn(Event) + 'a) -> Self {
+        self.on_event = Box::new(callback);
+        self
+    }
+
+    fn ops(&self) -> Ops<'_> {
+        Ops::new(&*self.on_event, self.dry_run)
+            .with_backup(self.backup.clone())
+            .with_preserve(self.preserve)
+            .with_verify(self.verify)
+            .with_interrupt(self.interrupt.clone())
+            .with_preflight(!self.skip_preflight)
+            .with_ignore(self.ignore.clone())
+    }
+
+    /// Applies `update` to the mapping output file, if there is one.
+    ///
+    /// `update` gets the directory of the mapping file and returns whether it changed anything.
+    fn update_mapping(
+        &self,
+        ops: &Ops,
+        update: impl FnOnce(&mut MappingFile, &Path) -> bool,
+    ) -> Result<()> {
+        let Some(out_file) = &self.mapping_output else {
+            return Ok(());
+        };
+        let base = MappingFile::base_dir(out_file)?;
+        let mut mapping_file = MappingFile::load_or_default(out_file)?;
+        if update(&mut mapping_file, &base)
+            && ops.perform(Action::WriteMapping {
+                file: out_file.clone(),
+            })
+        {
+            mapping_file.save(out_file)?;
+            ops.report(Event::MappingWritten {
+                file: out_file.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Links `dst` to `src` and returns the created link as a [`Mapping`].
+    ///
+    /// With move-and-link, `src` is moved to `dst` first and the link is created at `src`.
+    /// If the moved directory has ignored entries, they stay in `src` and everything else
+    /// in it is linked one by one, each with its own entry in the mapping output file.
+    pub fn link(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<Mapping> {
+        let ops = self.ops();
+        let result = self.link_with(&ops, src.as_ref(), dst.as_ref());
+        let saved = self.save_backups(&ops);
+        let mapping = result?;
+        saved?;
+        Ok(mapping)
+    }
+
+    fn link_with(&self, ops: &Ops, src: &Path, dst: &Path) -> Result<Mapping> {
+        let src = resolve(src)?;
+        let dst = resolve(dst)?;
+        if self.kind == LinkKind::Hard {
+            // Checked before moving anything
+            ops.check_same_device(&src, &dst)?;
+        }
+        let adopted =
+            !self.move_and_link && self.adopt && self.adopt_destination(ops, &src, &dst)?;
+        // Parts of a moved directory left out of the move because of ignored entries
+        let mut kept_parts = None;
+        let (target, link) = if self.move_and_link {
+            let ignore = ops.ignore(&src)?;
+            kept_parts = ignore.kept_parts(&src)?;
+            ops.move_kept(&src, &dst, self.force, &ignore)?;
+            if self.checksum_manifest {
+                self.write_checksums(ops, &dst)?;
+            }
+            (dst, src)
+        } else {
+            (src, dst)
+        };
+        // With ignored entries still at the source, only what has been moved is linked
+        let links = match &kept_parts {
+            Some(parts) => parts
+                .iter()
+                .map(|part| (target.join(part), link.join(part)))
+                .collect(),
+            None => vec![(target.clone(), link.clone())],
+        };
+        for (target, link) in &links {
+            ops.make_link(
+                target,
+                link,
+                self.kind,
+                self.force,
+                self.junction,
+                self.relative,
+            )?;
+        }
+        if self.move_and_link || adopted {
+            ops.finish_move(&target)?;
+        }
+        self.update_mapping(ops, |mapping_file, base| {
+            for (target, link) in &links {
+                let mapping = self.mapping(target, link);
+                mapping_file.upsert(self.entry(&mapping, base), base);
+            }
+            true
+        })?;
+        Ok(self.mapping(&target, &link))
+    }
+
+    /// Moves a real file or directory at `dst` to `src` so it can be linked back.
+    ///
+    /// Returns whether anything has been moved, links at `dst` are left to be replaced.
+    fn adopt_destination(&self, ops: &Ops, src: &Path, dst: &Path) -> Result<bool> {
+        if ops.link_target(dst).is_ok() || !ops.exists(dst) {
+            return Ok(false);
+        }
+        if ops.exists(src) {
+            if !(self.confirm_adopt)(src, dst) {
+                return Err(ImplinkError::DestinationExists {
+                    path: src.to_path_buf(),
+                });
+            }
+            ops.replace_existing(src)?;
+        } else if let Some(parent) = src.parent().filter(|parent| !ops.exists(parent)) {
+            ops.create_dir_all(parent)?;
+        }
+        ops.move_file_or_directory(dst, src, false)?;
+        Ok(true)
+    }
+
+    /// The entry written to the mapping file for `mapping`, in portable form if requested.
+    ///
+    /// Paths are escaped, so they are read back as they are instead of being expanded.
+    fn entry(&self, mapping: &Mapping, base: &Path) -> Mapping {
+        if !self.portable {
+            return Mapping {
+                src: escape_glob(&escape(&mapping.src)),
+                dst: escape(&mapping.dst),
+                ..mapping.clone()
+            };
+        }
+        Mapping {
+            src: escape_glob(&portable(Path::new(&mapping.src), Some(base))),
+            dst: portable(Path::new(&mapping.dst), Some(base)),
+            ..mapping.clone()
+        }
+    }
+
+    /// The mapping entry for a link created with the current settings.
+    fn mapping(&self, src: &Path, dst: &Path) -> Mapping {
+        Mapping {
+            src: src.to_string_lossy().into_owned(),
+            dst: dst.to_string_lossy().into_owned(),
+            force: self.force,
+            junction: self.junction,
+            kind: self.kind,
+            relative: self.relative,
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Links every entry of `package` into `target`, like GNU Stow.
+    ///
+    /// Entries which don't exist in `target` yet are linked as a whole. Directories which
+    /// already exist are descended into and their contents linked one by one, and links to
+    /// directories of other packages next to `package` are unfolded into real directories
+    /// holding links to their contents. Anything else in the way fails with
+    /// [`ImplinkError::StowConflict`] before anything is touched, unless [`Linker::force`]
+    /// is set.
+    ///
+    /// Every link is added to the mapping output file, links replaced while unfolding are
+    /// swapped for links to their contents.
+    pub fn stow(&self, package: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<Report> {
+        let package = resolve(package.as_ref())?;
+        let target = resolve(target.as_ref())?;
+        let ops = self.ops();
+        if !ops.is_dir(&package) {
+            return Err(ImplinkError::SourceMissing { path: package });
+        }
+        let ignore = ops.ignore(&package)?;
+        let steps = stow::plan(&package, &target, self.kind, self.force, &ignore)?;
+        if !ops.exists(&target) {
+            ops.create_dir_all(&target)?;
+        }
+        let mut report = Report::default();
+        // Unfolded links and the links to their contents which replace them
+        let mut unfolded: Vec<(PathBuf, Vec<Mapping>)> = Vec::new();
+        let result = steps.into_iter().try_for_each(|step| {
+            match step {
+                Step::Link { src, dst } => {
+                    // Replaces a link created while unfolding a parent
+                    report.entries.retain(|e| Path::new(&e.mapping.dst) != dst);
+                    ops.make_link(
+                        &src,
+                        &dst,
+                        self.kind,
+                        self.force,
+                        self.junction,
+                        self.relative,
+                    )?;
+                    report.entries.push(EntryReport {
+                        mapping: self.mapping(&src, &dst),
+                        status: EntryStatus::Linked,
+                    });
+                }
+                Step::CreateDir { dst } => {
+                    report.entries.retain(|e| Path::new(&e.mapping.dst) != dst);
+                    ops.create_dir_all(&dst)?;
+                }
+                Step::Linked { src, dst } => report.entries.push(EntryReport {
+                    mapping: self.mapping(&src, &dst),
+                    status: EntryStatus::AlreadyCorrect,
+                }),
+                Step::Unfold {
+                    dst,
+                    target,
+                    relative,
+                } => {
+                    report.entries.retain(|e| Path::new(&e.mapping.dst) != dst);
+                    ops.remove_link(&dst)?;
+                    ops.create_dir_all(&dst)?;
+                    let mut children = Vec::new();
+                    for src in stow::unfolded_entries(&target)? {
+                        let link = dst.join(src.file_name().unwrap_or_default());
+                        ops.make_link(&src, &link, LinkKind::Symlink, false, false, relative)?;
+                        let mapping = Mapping {
+                            kind: LinkKind::Symlink,
+                            relative,
+                            ..self.mapping(&src, &link)
+                        };
+                        report.entries.push(EntryReport {
+                            mapping: mapping.clone(),
+                            status: EntryStatus::Linked,
+                        });
+                        children.push(mapping);
+                    }
+                    unfolded.push((dst, children));
+                }
+            }
+            Ok(())
+        });
+        // Links created before a failure are recorded too
+        self.update_mapping(&ops, |mapping_file, base| {
+            for (dst, children) in &unfolded {
+                // Only links the mapping file knew about are replaced by their contents
+                let Some(old) = mapping_file.remove(dst, base) else {
+                    continue;
+                };
+                for child in children {
+                    let child = Mapping {
+                        force: old.force,
+                        junction: old.junction,
+                        ..child.clone()
+                    };
+                    mapping_file.upsert(self.entry(&child, base), base);
+                }
+            }
+            for entry in &report.entries {
+                let in_package = Path::new(&entry.mapping.src).starts_with(&package);
+                if in_package {
+                    mapping_file.upsert(self.entry(&entry.mapping, base), base);
+                }
+            }
+            true
+        })?;
+        let saved = self.save_backups(&ops);
+        result?;
+        saved?;
+        Ok(report)
+    }
+
+    /// Writes the hashes of the files at `dst` to `<dst>.b3sum`, with paths relative
+    /// to the directory of `dst`.
+    ///
+    /// Hashes taken while verifying copies are reused, anything else is hashed now.
+    fn write_checksums(&self, ops: &Ops, dst: &Path) -> Result<()> {
+        let mut name = dst.file_name().unwrap_or_default().to_os_string();
+        name.push(".b3sum");
+        let file = dst.with_file_name(name);
+        if !ops.perform(Action::WriteChecksums { file: file.clone() }) {
+            return Ok(());
+        }
+        let io_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |e| ImplinkError::Io { path, source: e }
+        };
+        // Backups moved to another filesystem are verified too, but don't belong here
+        let mut checksums = ops.take_checksums();
+        checksums.retain(|(path, _)| path.starts_with(dst));
+        if checksums.is_empty() {
+            checksums = mover::hash_tree(dst).map_err(io_error(dst))?;
+        }
+        checksums.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let base = dst.parent().unwrap_or(dst);
+        let contents: String = checksums
+            .iter()
+            .map(|(path, hash)| {
+                let relative = path.strip_prefix(base).unwrap_or(path);
+                format!("{}  {}\n", hash.to_hex(), relative.display())
+            })
+            .collect();
+        std::fs::write(&file, contents).map_err(io_error(&file))?;
+        ops.report(Event::ChecksumsWritten { file });
+        Ok(())
+    }
+
+    /// Removes the link at `link` pointing to `target`, moving the data back or
+    /// pruning empty parent directories if requested.
+    fn remove_link(&self, ops: &Ops, link: &Path, target: &Path) -> Result<()> {
+        if self.restore_data && !ops.exists(target) {
+            return Err(ImplinkError::SourceMissing {
+                path: target.to_path_buf(),
+            });
+        }
+        let relative = read_link(link).is_ok_and(|target| target.is_relative());
+        ops.remove_link(link)?;
+        if self.restore_data {
+            if let Err(e) = ops.move_file_or_directory(target, link, false) {
+                // The link is put back if nothing has been moved yet, the data stays reachable
+                if !ops.exists(link) {
+                    let _ = ops.make_symlink(target, link, false, self.junction, relative);
+                }
+                return Err(e);
+            }
+            ops.finish_move(link)?;
+            if ops.is_dir(target) {
+                ops.remove_empty_dir(target);
+            }
+        } else if self.prune {
+            ops.prune_empty_parents(link);
+        }
+        Ok(())
+    }
+
+    /// Adds an entry linking `dst` to `src` with the current settings to the mapping output
+    /// file, without creating the link. Returns the added entry.
+    pub fn add_mapping(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<Mapping> {
+        let dst = dst.as_ref();
+        let mut mapping = self.mapping(&resolve(src.as_ref())?, &resolve(dst)?);
+        // Resolving drops the trailing separator which links inside `dst`
+        let into_dir = dst.to_string_lossy().ends_with(['/', MAIN_SEPARATOR]);
+        if into_dir {
+            mapping.dst.push(MAIN_SEPARATOR);
+        }
+        self.update_mapping(&self.ops(), |mapping_file, base| {
+            let mut entry = self.entry(&mapping, base);
+            if into_dir && !entry.dst.ends_with(['/', MAIN_SEPARATOR]) {
+                entry.dst.push(MAIN_SEPARATOR);
+            }
+            mapping_file.upsert(entry, base);
+            true
+        })?;
+        Ok(mapping)
+    }
+
+    /// Drops the entry for `dst` from the mapping output file, leaving the link itself alone.
+    /// Returns the removed entry.
+    pub fn remove_mapping(&self, dst: impl AsRef<Path>) -> Result<Mapping> {
+        let dst = resolve(dst.as_ref())?;
+        let mut removed = None;
+        self.update_mapping(&self.ops(), |mapping_file, base| {
+            removed = mapping_file.remove(&dst, base);
+            removed.is_some()
+        })?;
+        removed.ok_or_else(|| ImplinkError::NoEntry {
+            path: dst,
+            file: self.mapping_output.clone().unwrap_or_default(),
+        })
+    }
+
+    /// Removes the link at `link`, which has to point to `target` if given.
+    ///
+    /// With [`Linker::restore_data`], the data the link pointed to is moved back to
+    /// where the link was, undoing move-and-link. The entry for the link is dropped
+    /// from the mapping output file. Returns the removed link as a [`Mapping`].
+    pub fn unlink(&self, link: impl AsRef<Path>, target: Option<&Path>) -> Result<Mapping> {
+        let link = resolve(link.as_ref())?;
+        let ops = self.ops();
+        let actual = ops.link_target(&link)?;
+        if let Some(expected) = target {
+            let expected = resolve(expected)?;
+            if actual != normalize(&expected) {
+                return Err(ImplinkError::LinkMismatch {
+                    path: link,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        let relative = read_link(&link).is_ok_and(|target| target.is_relative());
+        self.remove_link(&ops, &link, &actual)?;
+        self.update_mapping(&ops, |mapping_file, base| {
+            mapping_file.remove(&link, base).is_some()
+        })?;
+        Ok(Mapping {
+            src: actual.to_string_lossy().into_owned(),
+            dst: link.to_string_lossy().into_owned(),
+            force: self.force,
+            junction: self.junction,
+            kind: LinkKind::Symlink,
+            relative,
+            exclude: Vec::new(),
+        })
+    }
+
+    /// Removes the link of a single mapping entry if it still points to its source.
+    fn unlink_entry(&self, ops: &Ops, mapping: &Mapping, base: &Path) -> Result<EntryStatus> {
+        let (src, dst) = mapping.resolve(base)?;
+        if mapping.kind == LinkKind::Hard {
+            return self.unlink_hard_entry(ops, &src, &dst);
+        }
+        let target = match ops.link_target(&dst) {
+            Ok(target) => target,
+            Err(ImplinkError::NotALink { .. }) => {
+                return Ok(EntryStatus::Skipped(
+                    "replaced by a real file or directory".to_string(),
+                ))
+            }
+            Err(ImplinkError::Io { source, .. }) if source.kind() == ErrorKind::NotFound => {
+                return Ok(EntryStatus::Skipped("link doesn't exist".to_string()))
+            }
+            Err(e) => return Err(e),
+        };
+        if target != normalize(&src) {
+            return Ok(EntryStatus::Skipped(format!(
+                "points to '{}' instead",
+                target.display()
+            )));
+        }
+        self.remove_link(ops, &dst, &src)?;
+        Ok(EntryStatus::Unlinked)
+    }
+
+    /// Removes the hard link or mirrored tree of a mapping entry if it still matches its source.
+    ///
+    /// With [`Linker::restore_data`], the source is removed instead, as the link already
+    /// holds all of its data. Sources with ignored entries, which the mirror doesn't have,
+    /// are skipped then.
+    fn unlink_hard_entry(&self, ops: &Ops, src: &Path, dst: &Path) -> Result<EntryStatus> {
+        if !ops.exists(dst) {
+            return Ok(EntryStatus::Skipped("link doesn't exist".to_string()));
+        }
+        if !ops.is_linked(dst, src, LinkKind::Hard) {
+            return Ok(EntryStatus::Skipped(
+                "isn't hard linked to the source anymore".to_string(),
+            ));
+        }
+        if self.restore_data {
+            if ops.ignore(src)?.leaves_out_any(src)? {
+                return Ok(EntryStatus::Skipped(
+                    "the source has ignored entries which aren't in the link".to_string(),
+                ));
+            }
+            ops.rm_rf(src)?;
+        } else {
+            ops.remove_hard_link(dst)?;
+            if self.prune {
+                ops.prune_empty_parents(dst);
+            }
+        }
+        Ok(EntryStatus::Unlinked)
+    }
+
+    /// Removes every link in a mapping file which still points to its source.
+    ///
+    /// Destinations which have become real files or point elsewhere are skipped.
+    /// The mapping file itself is left unchanged.
+    pub fn unlink_all(&self, file: impl AsRef<Path>) -> Result<Report> {
+        let mapping_file = MappingFile::load(file.as_ref())?;
+        let base = MappingFile::base_dir(file.as_ref())?;
+        let ops = self.ops();
+        let mut report = Report::default();
+        for entry in expand_all(mapping_file.mapping, &base) {
+            let mapping = match entry {
+                Ok((mapping, _)) => mapping,
+                Err(entry) => {
+                    report.entries.push(entry);
+                    continue;
+                }
+            };
+            let status = self
+                .unlink_entry(&ops, &mapping, &base)
+                .unwrap_or_else(EntryStatus::Failed);
+            let interrupted = matches!(
+                status,
+                EntryStatus::Failed(ImplinkError::Interrupted { .. })
+            );
+            report.entries.push(EntryReport { mapping, status });
+            if interrupted {
+                break;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Links a single mapping entry, leaving it alone if it is already in place.
+    fn restore_entry(
+        ops: &Ops,
+        mapping: &Mapping,
+        inside: bool,
+        base: &Path,
+    ) -> Result<EntryStatus> {
+        let (src, dst) = mapping.resolve(base)?;
+        if ops.is_linked(&dst, &src, mapping.kind) {
+            return Ok(EntryStatus::AlreadyCorrect);
+        }
+        // The directory a glob or `dst/` entry links into may not exist yet
+        if let Some(parent) = dst.parent().filter(|parent| inside && !ops.exists(parent)) {
+            if ops.exists(&src) {
+                ops.create_dir_all(parent)?;
+            }
+        }
+        ops.make_link(
+            &src,
+            &dst,
+            mapping.kind,
+            mapping.force,
+            mapping.junction,
+            mapping.relative,
+        )?;
+        Ok(EntryStatus::Linked)
+    }
+
+    /// Restores every link in a mapping file, stopping at the first failure.
+    ///
+    /// Glob entries are expanded into one link per match, see [`Mapping::expand`], one
+    /// matching nothing fails like a missing source.
+    /// Each entry uses its own `force`, `junction` and `kind` settings. With [`Linker::atomic`],
+    /// everything done before the failure is undone.
+    pub fn restore(&self, file: impl AsRef<Path>) -> Result<Vec<Mapping>> {
+        let mapping_file = MappingFile::load(file.as_ref())?;
+        let base = MappingFile::base_dir(file.as_ref())?;
+        let ops = if self.atomic {
+            self.ops().journaled()
+        } else {
+            self.ops()
+        };
+        let result = mapping_file.mapping.iter().try_for_each(|mapping| {
+            let expanded = mapping.expand(&base)?;
+            if expanded.is_empty() {
+                return Err(ImplinkError::SourceMissing {
+                    path: mapping.resolve(&base)?.0,
+                });
+            }
+            expanded.iter().try_for_each(|entry| {
+                Linker::restore_entry(&ops, entry, mapping.links_inside(), &base).map(|_| ())
+            })
+        });
+        let result = match result {
+            Ok(_) => ops.commit(),
+            Err(e) => {
+                ops.rollback();
+                Err(e)
+            }
+        };
+        let saved = self.save_backups(&ops);
+        result?;
+        saved?;
+        Ok(mapping_file.mapping)
+    }
+
+    /// Attempts every link in a mapping file, even if some of them fail.
+    ///
+    /// Entries whose source doesn't exist are skipped. Only failing to read the
+    /// mapping file itself is returned as an error.
+    pub fn restore_all(&self, file: impl AsRef<Path>) -> Result<Report> {
+        let mapping_file = MappingFile::load(file.as_ref())?;
+        let base = MappingFile::base_dir(file.as_ref())?;
+        let ops = self.ops();
+        let mut report = Report::default();
+        for entry in expand_all(mapping_file.mapping, &base) {
+            let (mapping, inside) = match entry {
+                Ok(entry) => entry,
+                Err(entry) => {
+                    report.entries.push(entry);
+                    continue;
+                }
+            };
+            let status = match Linker::restore_entry(&ops, &mapping, inside, &base) {
+                Ok(status) => status,
+                Err(e @ ImplinkError::SourceMissing { .. }) => EntryStatus::Skipped(e.to_string()),
+                Err(e) => EntryStatus::Failed(e),
+            };
+            let interrupted = matches!(
+                status,
+                EntryStatus::Failed(ImplinkError::Interrupted { .. })
+            );
+            report.entries.push(EntryReport { mapping, status });
+            if interrupted {
+                break;
+            }
+        }
+        self.save_backups(&ops)?;
+        Ok(report)
+    }
+
+    /// Puts the most recent backup of `original` back in its place.
+    ///
+    /// A link at `original` is removed first, anything else is only replaced with `force`.
+    /// The backup is dropped from the backup manifest.
+    pub fn restore_backup(&self, original: impl AsRef<Path>) -> Result<BackupEntry> {
+        let original = resolve(original.as_ref())?;
+        let manifest_path = self.manifest_path()?;
+        let mut manifest = BackupManifest::load_or_default(&manifest_path)?;
+        let Some((index, entry)) = manifest.latest(&original) else {
+            return Err(ImplinkError::NoBackup { path: original });
+        };
+        let entry = entry.clone();
+        let ops = self.ops();
+        if !ops.exists(&entry.backup) {
+            return Err(ImplinkError::SourceMissing { path: entry.backup });
+        }
+        if ops.link_target(&original).is_ok() {
+            ops.remove_link(&original)?;
+        } else if ops.exists(&original) {
+            if !self.force {
+                return Err(ImplinkError::DestinationExists { path: original });
+            }
+            ops.rm_rf(&original)?;
+        }
+        ops.move_file_or_directory(&entry.backup, &original, false)?;
+        ops.finish_move(&original)?;
+        if ops.is_dir(&entry.backup) {
+            ops.remove_empty_dir(&entry.backup);
+        }
+        manifest.backups.remove(index);
+        if ops.perform(Action::WriteManifest {
+            file: manifest_path.clone(),
+        }) {
+            manifest.save(&manifest_path)?;
+        }
+        Ok(entry)
+    }
+
+    fn manifest_path(&self) -> Result<PathBuf> {
+        match &self.backup_manifest {
+            Some(path) => Ok(path.clone()),
+            None => BackupManifest::default_path(),
+        }
+    }
+
+    /// Adds the backups made by `ops` to the backup manifest.
+    fn save_backups(&self, ops: &Ops) -> Result<()> {
+        let backups = ops.take_backups();
+        if backups.is_empty() {
+            return Ok(());
+        }
+        let manifest_path = self.manifest_path()?;
+        let mut manifest = BackupManifest::load_or_default(&manifest_path)?;
+        manifest.backups.extend(backups);
+        manifest.save(&manifest_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn restore_data_keeps_sources_with_ignored_entries() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let pkg = dir.join("pkg");
+        create_dir_all(pkg.join("sub")).unwrap();
+        write(pkg.join("a"), "a").unwrap();
+        write(pkg.join("sub/b"), "b").unwrap();
+        write(pkg.join("secret"), "secret").unwrap();
+        write(pkg.join(".implinkignore"), "secret\n").unwrap();
+        let map = dir.join("map.json");
+        Linker::new()
+            .kind(LinkKind::Hard)
+            .mapping_output(&map)
+            .link(&pkg, dir.join("mirror"))
+            .unwrap();
+        assert!(!dir.join("mirror/secret").exists());
+
+        let report = Linker::new().restore_data(true).unlink_all(&map).unwrap();
+        assert!(matches!(report.entries[0].status, EntryStatus::Skipped(_)));
+        assert!(pkg.join("secret").exists());
+        assert!(pkg.join(".implinkignore").exists());
+    }
+
+    #[test]
+    fn restore_data_removes_fully_mirrored_sources() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let pkg = dir.join("pkg");
+        create_dir_all(&pkg).unwrap();
+        write(pkg.join("a"), "a").unwrap();
+        let map = dir.join("map.json");
+        Linker::new()
+            .kind(LinkKind::Hard)
+            .mapping_output(&map)
+            .link(&pkg, dir.join("mirror"))
+            .unwrap();
+
+        let report = Linker::new().restore_data(true).unlink_all(&map).unwrap();
+        assert!(matches!(report.entries[0].status, EntryStatus::Unlinked));
+        assert!(!pkg.exists());
+        assert!(dir.join("mirror/a").exists());
+    }
+
+    #[test]
+    fn restores_generated_entries_with_glob_characters() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        create_dir_all(dir.join("src/cfg[1]")).unwrap();
+        create_dir_all(dir.join("home")).unwrap();
+        let map = dir.join("map.json");
+        Linker::new()
+            .mapping_output(&map)
+            .link(dir.join("src/cfg[1]"), dir.join("home/cfg"))
+            .unwrap();
+        std::fs::remove_file(dir.join("home/cfg")).unwrap();
+
+        Linker::new().restore(&map).unwrap();
+        assert_eq!(
+            read_link(dir.join("home/cfg")).unwrap(),
+            dir.join("src/cfg[1]")
+        );
+    }
+
+    #[test]
+    fn restore_fails_when_a_glob_matches_nothing() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let map = dir.join("map.json");
+        write(
+            &map,
+            r#"{ "mapping": [{ "src": "fonts/*.ttf", "dst": "out/", "force": false, "junction": false }] }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Linker::new().restore(&map),
+            Err(ImplinkError::SourceMissing { .. })
+        ));
+        let report = Linker::new().restore_all(&map).unwrap();
+        assert!(matches!(report.entries[0].status, EntryStatus::Skipped(_)));
+    }
+
+    /// Writes a mapping file with `(src, dst, force)` entries.
+    fn write_mapping(path: &Path, entries: &[(&str, &str, bool)]) {
+        let mapping = entries
+            .iter()
+            .map(|(src, dst, force)| {
+                format!(
+                    r#"{{ "src": "{}", "dst": "{}", "force": {}, "junction": false }}"#,
+                    src, dst, force
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        write(path, format!(r#"{{ "mapping": [{}] }}"#, mapping)).unwrap();
+    }
+
+    #[test]
+    fn atomic_restore_puts_back_replaced_destinations() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        create_dir_all(dir.join("home/empty")).unwrap();
+        write(dir.join("a"), "a").unwrap();
+        write(dir.join("b"), "b").unwrap();
+        write(dir.join("home/a"), "old").unwrap();
+        let map = dir.join("map.json");
+        write_mapping(
+            &map,
+            &[
+                ("a", "home/a", true),
+                ("b", "home/empty", false),
+                ("missing", "home/c", false),
+            ],
+        );
+
+        let result = Linker::new().atomic(true).restore(&map);
+        assert!(matches!(result, Err(ImplinkError::SourceMissing { .. })));
+        assert_eq!(std::fs::read_to_string(dir.join("home/a")).unwrap(), "old");
+        assert!(dir.join("home/empty").is_dir());
+        assert_eq!(std::fs::read_dir(dir.join("home")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn atomic_restore_puts_back_backups() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        create_dir_all(dir.join("home")).unwrap();
+        write(dir.join("a"), "a").unwrap();
+        write(dir.join("home/a"), "old").unwrap();
+        let map = dir.join("map.json");
+        write_mapping(&map, &[("a", "home/a", true), ("missing", "home/c", false)]);
+
+        let manifest = dir.join("backups.json");
+        let result = Linker::new()
+            .atomic(true)
+            .backup(Some(BackupMode::default()))
+            .backup_manifest(&manifest)
+            .restore(&map);
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(dir.join("home/a")).unwrap(), "old");
+        assert_eq!(std::fs::read_dir(dir.join("home")).unwrap().count(), 1);
+        assert!(!manifest.exists());
+    }
+
+    #[test]
+    fn atomic_restore_removes_created_parents() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        write(dir.join("a"), "a").unwrap();
+        let map = dir.join("map.json");
+        write_mapping(&map, &[("a", "new/sub/", false), ("missing", "c", false)]);
+
+        assert!(Linker::new().atomic(true).restore(&map).is_err());
+        assert!(!dir.join("new").exists());
+    }
+}
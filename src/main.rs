@@ -1,54 +1,413 @@
-use clap::Parser;
-use fs_extra::{dir, file, file::move_file_with_progress};
-use serde::{Deserialize, Serialize};
-use std::fs::{
-    create_dir_all, read_to_string, remove_dir, remove_dir_all, remove_file, rename, write,
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use implink::{
+    Action, BackupManifest, BackupMode, EntryState, EntryStatus, Event, ImplinkError, LinkKind,
+    LinkState, Linker, MappingFile, Preserve, Report, DEFAULT_BACKUP_SUFFIX,
 };
-#[cfg(not(target_os = "windows"))]
-use std::os::unix::fs::symlink;
-#[cfg(target_os = "windows")]
-use std::os::windows::fs::{symlink_dir, symlink_file};
-use std::path::{absolute, PathBuf};
-#[cfg(target_os = "windows")]
-use std::process::Command;
+use std::cell::RefCell;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::{absolute, Path};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use terminal_size::terminal_size;
 
 /// File symlinking made easy.
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    override_usage = "implink [OPTIONS] <COMMAND>",
+    arg_required_else_help = true
+)]
 struct Args {
-    /// Source file or directory to be symlinked
-    src: Option<String>,
-    /// Symlink location
-    dst: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    legacy: LegacyArgs,
+    /// Print what would be done without changing anything
+    #[arg(short = 'n', long, global = true)]
+    dry_run: bool,
+    /// Print the dry run plan as JSON
+    #[arg(long, requires = "dry_run", global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a link at DST pointing to SRC
+    Link {
+        /// Source file or directory to be linked
+        src: String,
+        /// Link location
+        dst: String,
+        #[command(flatten)]
+        link: LinkOptions,
+        /// Move a destination which is a real file or directory to the source and link it back,
+        /// asking before replacing an existing source
+        #[arg(long)]
+        adopt: bool,
+        /// Leave entries matching this gitignore-style pattern out when mirroring directories
+        /// with --kind hard, on top of their .implinkignore files (can be repeated)
+        #[arg(long, value_name = "PATTERN")]
+        ignore: Vec<String>,
+        #[command(flatten)]
+        backup: BackupOptions,
+        #[command(flatten)]
+        output: MappingOutput,
+    },
+    /// Move SRC to DST and create a link at SRC pointing to it
+    Move {
+        /// File or directory to move, the link is created in its place
+        src: String,
+        /// Where to move it
+        dst: String,
+        #[command(flatten)]
+        link: LinkOptions,
+        #[command(flatten)]
+        moving: MoveOptions,
+        #[command(flatten)]
+        backup: BackupOptions,
+        #[command(flatten)]
+        output: MappingOutput,
+    },
+    /// Recreate every link in a mapping file
+    Restore {
+        /// Mapping file to restore
+        file: String,
+        /// Keep restoring the remaining entries when one fails, then print a summary
+        #[arg(short, long)]
+        keep_going: bool,
+        /// Undo every change made by the restore if one of the entries fails
+        #[arg(short, long, conflicts_with = "keep_going")]
+        atomic: bool,
+        #[command(flatten)]
+        backup: BackupOptions,
+    },
+    /// Check whether the links in a mapping file are still in place, without changing anything
+    Status {
+        /// Mapping file to check
+        file: String,
+    },
+    /// Remove a symlink or junction, optionally moving the data it points to back in its place
+    ///
+    /// Without a link, removes every link in the mapping file which still points to its source.
+    Unlink {
+        /// Link to remove
+        #[arg(required_unless_present = "mapping")]
+        link: Option<String>,
+        /// Only remove the link if it points here
+        target: Option<String>,
+        /// Move the data the link points to back to where the link was
+        #[arg(short = 'd', long)]
+        restore_data: bool,
+        /// Remove parent directories which become empty
+        #[arg(short, long)]
+        prune: bool,
+        /// Mapping file to remove the links of, or to drop the entry for the link from
+        #[arg(short = 'r', long)]
+        mapping: Option<String>,
+    },
+    /// Link every entry of a package directory into a target directory, like GNU Stow
+    ///
+    /// Directories which already exist in the target are descended into, links to
+    /// directories of other packages next to this one are unfolded.
+    Stow {
+        /// Package directory to link the contents of
+        package: String,
+        /// Directory to create the links in
+        target: String,
+        #[command(flatten)]
+        link: LinkOptions,
+        /// Leave entries matching this gitignore-style pattern out of the package, on top of
+        /// its .implinkignore files (can be repeated)
+        #[arg(long, value_name = "PATTERN")]
+        ignore: Vec<String>,
+        #[command(flatten)]
+        backup: BackupOptions,
+        #[command(flatten)]
+        output: MappingOutput,
+    },
+    /// Add, remove or list the entries of a mapping file without touching any link
+    Mapping {
+        #[command(subcommand)]
+        command: MappingCommand,
+    },
+    /// Put the latest backup of a path back in its place, or list all backups
+    RestoreBackup {
+        /// Path the backup was made of
+        path: Option<String>,
+        /// Replace whatever is at the path now, even if it isn't a link
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MappingCommand {
+    /// Add an entry for a link at DST pointing to SRC, replacing the entry for DST if there is one
+    Add {
+        /// Mapping file to add the entry to, created if needed
+        file: String,
+        /// Source file or directory, or a glob pattern
+        src: String,
+        /// Link location
+        dst: String,
+        #[command(flatten)]
+        link: LinkOptions,
+        /// Write the paths relative to the mapping file or to the home directory
+        #[arg(short, long)]
+        portable: bool,
+    },
+    /// Remove the entry for a link
+    Rm {
+        /// Mapping file to remove the entry from
+        file: String,
+        /// Link location of the entry
+        dst: String,
+    },
+    /// List the entries of a mapping file
+    Ls {
+        /// Mapping file to list
+        file: String,
+    },
+}
+
+/// How links are created.
+#[derive(clap::Args, Debug)]
+struct LinkOptions {
     /// Force overwrite of existing destination
     #[arg(short, long)]
     force: bool,
     /// Use NTFS junction for directories on Windows
     #[arg(short, long)]
     junction: bool,
-    /// Move file or directory to the destination and create a symlink back
-    #[arg(short, long)]
-    move_and_link: bool,
-    /// Generate a mapping file
-    #[arg(short, long)]
+    /// Kind of link to create: symlink, or hard to hard link files and mirror directories
+    /// with hard linked files
+    #[arg(long, value_name = "KIND", default_value = "symlink")]
+    kind: LinkKind,
+    /// Store the path to the source relative to the link's directory, so the link keeps
+    /// working when both are moved together
+    #[arg(long)]
+    relative: bool,
+}
+
+impl LinkOptions {
+    fn apply(self, linker: Linker<'_>) -> Linker<'_> {
+        linker
+            .force(self.force)
+            .junction(self.junction)
+            .kind(self.kind)
+            .relative(self.relative)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct BackupOptions {
+    /// Back up destinations replaced by --force instead of removing them, either by
+    /// adding a suffix (default ".implink-bak") or by moving them into a directory
+    #[arg(
+        short,
+        long,
+        value_name = "SUFFIX|DIR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = DEFAULT_BACKUP_SUFFIX
+    )]
+    backup: Option<String>,
+}
+
+impl BackupOptions {
+    fn apply(self, linker: Linker<'_>) -> Linker<'_> {
+        linker.backup(self.backup.as_deref().map(parse_backup))
+    }
+
+    /// Whether replaced destinations are moved into a directory, maybe on another filesystem.
+    fn moves_data(&self) -> bool {
+        matches!(
+            self.backup.as_deref().map(parse_backup),
+            Some(BackupMode::Dir(_))
+        )
+    }
+}
+
+/// How data is moved before it gets linked.
+#[derive(clap::Args, Debug)]
+struct MoveOptions {
+    /// Metadata to keep when moving to another filesystem: a comma separated list of
+    /// mode, timestamps, ownership and xattr, or all / none
+    #[arg(long, value_name = "LIST", default_value = "all")]
+    preserve: Preserve,
+    /// Compare every file copied to another filesystem to its source before deleting the source
+    #[arg(long)]
+    verify: bool,
+    /// Also write the hashes of the moved files next to the destination, as <DST>.b3sum
+    #[arg(long, requires = "verify")]
+    checksums: bool,
+    /// Leave entries matching this gitignore-style pattern at the source when moving
+    /// directories, on top of their .implinkignore files (can be repeated)
+    #[arg(long, value_name = "PATTERN")]
+    ignore: Vec<String>,
+    /// Don't check free space and permissions before moving
+    #[arg(long)]
+    skip_preflight: bool,
+}
+
+impl MoveOptions {
+    fn apply(self, linker: Linker<'_>) -> Linker<'_> {
+        linker
+            .move_and_link(true)
+            .preserve(self.preserve)
+            .verify(self.verify)
+            .checksum_manifest(self.checksums)
+            .ignore(self.ignore)
+            .skip_preflight(self.skip_preflight)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct MappingOutput {
+    /// Add the link to a mapping file, creating it if needed
+    #[arg(short, long, value_name = "FILE")]
     generate_mapping: Option<String>,
-    /// Restore mapping from a file
-    #[arg(short, long)]
-    restore_mapping: Option<String>,
+    /// Write paths to the mapping file relative to it or to the home directory
+    #[arg(short, long, requires = "generate_mapping")]
+    portable: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Mapping {
-    src: String,
-    dst: String,
+impl MappingOutput {
+    fn apply(self, linker: Linker<'_>) -> Linker<'_> {
+        let linker = linker.portable(self.portable);
+        match self.generate_mapping {
+            Some(out_file) => linker.mapping_output(out_file),
+            None => linker,
+        }
+    }
+}
+
+/// The flags from before there were subcommands, still accepted but hidden from the help.
+#[derive(clap::Args, Debug)]
+struct LegacyArgs {
+    #[arg(hide = true)]
+    src: Option<String>,
+    #[arg(hide = true)]
+    dst: Option<String>,
+    #[arg(short, long, hide = true)]
     force: bool,
+    #[arg(short, long, hide = true)]
     junction: bool,
+    #[arg(long, default_value = "symlink", hide = true)]
+    kind: LinkKind,
+    #[arg(long, hide = true)]
+    relative: bool,
+    #[arg(short, long, hide = true)]
+    move_and_link: bool,
+    #[arg(long, conflicts_with = "move_and_link", hide = true)]
+    adopt: bool,
+    #[arg(short, long, hide = true)]
+    generate_mapping: Option<String>,
+    #[arg(short, long, requires = "generate_mapping", hide = true)]
+    portable: bool,
+    #[arg(short, long, hide = true)]
+    restore_mapping: Option<String>,
+    #[arg(short, long, requires = "restore_mapping", hide = true)]
+    keep_going: bool,
+    #[arg(
+        short,
+        long,
+        requires = "restore_mapping",
+        conflicts_with = "keep_going",
+        hide = true
+    )]
+    atomic: bool,
+    #[arg(
+        short,
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = DEFAULT_BACKUP_SUFFIX,
+        hide = true
+    )]
+    backup: Option<String>,
+    #[arg(long, default_value = "all", hide = true)]
+    preserve: Preserve,
+    #[arg(long, hide = true)]
+    verify: bool,
+    #[arg(long, requires = "verify", hide = true)]
+    checksums: bool,
+    #[arg(long, hide = true)]
+    ignore: Vec<String>,
+    #[arg(long, hide = true)]
+    skip_preflight: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct MappingFile {
-    mapping: Vec<Mapping>,
+impl Command {
+    /// Whether the command may move data, which a first Ctrl-C stops cleanly.
+    fn moves_data(&self) -> bool {
+        match self {
+            Command::Move { .. } | Command::RestoreBackup { .. } => true,
+            Command::Link { adopt, backup, .. } => *adopt || backup.moves_data(),
+            Command::Restore { backup, .. } | Command::Stow { backup, .. } => backup.moves_data(),
+            Command::Unlink { restore_data, .. } => *restore_data,
+            Command::Status { .. } | Command::Mapping { .. } => false,
+        }
+    }
+}
+
+impl LegacyArgs {
+    /// The subcommand the flags stand for, `None` if they don't name anything to do.
+    fn into_command(self) -> Option<Command> {
+        let link = LinkOptions {
+            force: self.force,
+            junction: self.junction,
+            kind: self.kind,
+            relative: self.relative,
+        };
+        let backup = BackupOptions {
+            backup: self.backup,
+        };
+        if let Some(file) = self.restore_mapping {
+            return Some(Command::Restore {
+                file,
+                keep_going: self.keep_going,
+                atomic: self.atomic,
+                backup,
+            });
+        }
+        let (src, dst) = (self.src?, self.dst?);
+        let output = MappingOutput {
+            generate_mapping: self.generate_mapping,
+            portable: self.portable,
+        };
+        Some(if self.move_and_link {
+            Command::Move {
+                src,
+                dst,
+                link,
+                moving: MoveOptions {
+                    preserve: self.preserve,
+                    verify: self.verify,
+                    checksums: self.checksums,
+                    ignore: self.ignore,
+                    skip_preflight: self.skip_preflight,
+                },
+                backup,
+                output,
+            }
+        } else {
+            Command::Link {
+                src,
+                dst,
+                link,
+                adopt: self.adopt,
+                ignore: self.ignore,
+                backup,
+                output,
+            }
+        })
+    }
 }
 
 fn clear_last_line() {
@@ -61,381 +420,470 @@ fn clear_last_line() {
     print!("\r{}\r", " ".repeat(width));
 }
 
-/// Actual symlink implementation for Windows
-#[cfg(target_os = "windows")]
-fn _make_symlink(src: &PathBuf, dst: &PathBuf, use_junction: bool) -> Result<(), std::io::Error> {
-    if src.is_dir() {
-        if use_junction {
-            return junction::create(src, dst);
-        }
-        return symlink_dir(src, dst);
-    }
-    return symlink_file(src, dst);
-}
-
-/// Actual symlink implementation for other platforms
-#[cfg(not(target_os = "windows"))]
-fn _make_symlink(src: &PathBuf, dst: &PathBuf, _: bool) -> Result<(), std::io::Error> {
-    symlink(src, dst)
-}
-
-fn move_file_or_directory(src: &PathBuf, dst: &PathBuf, force: bool) -> Result<(), String> {
-    if src.is_file() {
-        match rename(src, dst) {
-            Ok(_) => (),
-            Err(e) => {
-                return Err(format!(
-                    "Failed to move file '{}' to '{}': {}",
-                    src.display(),
-                    dst.display(),
-                    e
-                ))
-            }
-        }
-    } else {
-        let dir_options = dir::CopyOptions {
-            buffer_size: 1024 * 1024,
-            ..Default::default()
-        };
-        let file_options = file::CopyOptions {
-            buffer_size: 1024 * 1024,
-            ..Default::default()
-        };
-        let dir_handler = |process_info: dir::TransitProcess| {
+fn print_event(event: Event) {
+    match event {
+        Event::MoveProgress { file, dst, percent } => {
             clear_last_line();
             print!(
                 "Moving '{}' to '{}'... {}%",
-                process_info.file_name,
+                file.display(),
                 dst.display(),
-                process_info.copied_bytes * 100 / process_info.total_bytes
+                percent
             );
-            dir::TransitProcessResult::ContinueOrAbort
-        };
-        if !dst.exists() {
-            match create_dir_all(dst) {
-                Ok(_) => (),
-                Err(e) => {
-                    return Err(format!(
-                        "Failed to create destination directory '{}': {}",
-                        dst.display(),
-                        e
-                    ))
-                }
-            }
-        } else {
-            if !dst.read_dir().unwrap().next().is_none() {
-                if !force {
-                    return Err(format!(
-                        "Destination directory '{}' is not empty",
-                        dst.display()
-                    ));
-                }
-                match remove_dir_all(dst) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        return Err(format!(
-                            "Failed to remove destination directory '{}': {}",
-                            dst.display(),
-                            e
-                        ))
-                    }
-                }
-                create_dir_all(dst).unwrap();
-            }
+            let _ = stdout().flush();
         }
-        for path in src.read_dir().unwrap() {
-            let path = path.unwrap().path();
-            if path.is_dir() {
-                match dir::move_dir_with_progress(path, dst, &dir_options, dir_handler) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        return Err(format!(
-                            "Failed to move directory '{}' to '{}': {}",
-                            src.display(),
-                            dst.display(),
-                            e
-                        ));
-                    }
-                }
-            } else {
-                let path_clone = path.clone();
-                let path_clone2 = path.clone();
-                let abc = path_clone2.strip_prefix(src).unwrap();
-                match move_file_with_progress(
-                    path,
-                    dst.join(abc),
-                    &file_options,
-                    |process_info: file::TransitProcess| {
-                        clear_last_line();
-                        print!(
-                            "Moving '{}' to '{}'... {}%",
-                            path_clone.display(),
-                            dst.display(),
-                            process_info.copied_bytes * 100 / process_info.total_bytes
-                        );
-                    },
-                ) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        return Err(format!(
-                            "Failed to move directory '{}' to '{}': {}",
-                            src.display(),
-                            dst.display(),
-                            e
-                        ));
-                    }
-                }
-            }
+        Event::Moved { src, dst } => {
+            println!("\nMoved '{}' to '{}'.", src.display(), dst.display());
+        }
+        Event::Resuming { src, dst } => {
+            println!(
+                "Resuming the interrupted move of '{}' to '{}'...",
+                src.display(),
+                dst.display()
+            );
+        }
+        Event::Retrying { dst, reason } => {
+            println!("Error: {}", reason);
+            println!(
+                "Trying to remove destination file or directory '{}'...",
+                dst.display()
+            );
+        }
+        Event::Symlinked { src, dst } => {
+            println!("Symlinked '{}' to '{}'", src.display(), dst.display());
+        }
+        Event::HardLinked { src, dst } => {
+            println!("Hard linked '{}' to '{}'", src.display(), dst.display());
+        }
+        Event::BackedUp { src, backup } => {
+            println!("Backed up '{}' to '{}'", src.display(), backup.display());
+        }
+        Event::Unlinked { dst } => {
+            println!("Removed link '{}'", dst.display());
+        }
+        Event::MappingWritten { file } => {
+            println!("Mapping file has been written to '{}'.", file.display());
+        }
+        Event::ChecksumsWritten { file } => {
+            println!("Checksums have been written to '{}'.", file.display());
+        }
+        Event::RolledBack { path } => {
+            println!("Rolled back '{}'", path.display());
+        }
+        Event::RollbackFailed { path, reason } => {
+            eprintln!("Failed to roll back '{}': {}", path.display(), reason);
+        }
+        Event::Planned(action) => {
+            println!("{}", action);
         }
     }
-    println!("\nMoved '{}' to '{}'.", src.display(), dst.display());
-    Ok(())
 }
 
-fn rm_rf(dst: &PathBuf) -> Result<(), String> {
-    let mut result: Result<(), String>;
-    if dst.is_file() {
-        match remove_file(dst) {
-            Ok(_) => {
-                result = Ok(());
-            },
-            Err(e) => {
-                result = Err(format!(
-                    "Failed to remove destination file '{}': {}",
-                    dst.display(),
-                    e
-                ));
-            }
+fn print_report(report: &Report) {
+    println!();
+    for entry in &report.entries {
+        let (status, reason) = match &entry.status {
+            EntryStatus::Linked => ("linked", None),
+            EntryStatus::Unlinked => ("unlinked", None),
+            EntryStatus::AlreadyCorrect => ("already correct", None),
+            EntryStatus::Skipped(reason) => ("skipped", Some(reason.clone())),
+            EntryStatus::Failed(e) => ("failed", Some(e.to_string())),
+        };
+        print!(
+            "{:<16} {} -> {}",
+            status, entry.mapping.dst, entry.mapping.src
+        );
+        match reason {
+            Some(reason) => println!(" ({})", reason),
+            None => println!(),
         }
+    }
+    let counts = [
+        (report.linked(), "linked"),
+        (report.unlinked(), "unlinked"),
+        (report.already_correct(), "already correct"),
+        (report.skipped(), "skipped"),
+        (report.failed(), "failed"),
+    ];
+    let summary: Vec<String> = counts
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, label)| format!("{} {}", count, label))
+        .collect();
+    if summary.is_empty() {
+        println!("\nNothing to do");
     } else {
-        match remove_dir_all(dst) {
-            Ok(_) => {
-                result = Ok(());
-            },
-            Err(e) => {
-                result = Err(format!(
-                    "Failed to remove destination directory '{}': {}",
-                    dst.display(),
-                    e
-                ));
-            }
-        }
+        println!("\n{}", summary.join(", "));
     }
-    // I do love Windows bro ("truly" :D)
-    // Fallback to "del" command which works on Windows :D
-    // del documentation: https://learn.microsoft.com/en-us/windows-server/administration/windows-commands/del
-    #[cfg(target_os = "windows")]
-    {
-        if result.is_err() {
-            // This should be equivalent to "rm -rf" on Unix-like systems
-            let mut command = Command::new("cmd");
-            command.args(["/C", "del", "/f", "/q", "/s", dst.to_str().unwrap()]);
-            match command.output() {
-                Ok(_) => {
-                    result = Ok(());
-                },
-                Err(e) => {
-                    result = Err(format!(
-                        "Failed to remove destination file or directory '{}': {}",
-                        dst.display(),
-                        e
-                    ));
-                }
+}
+
+fn print_status(entries: &[EntryState]) {
+    for entry in entries {
+        let (state, detail) = match &entry.state {
+            LinkState::Ok => ("ok", None),
+            LinkState::MissingLink => ("missing link", None),
+            LinkState::PointsElsewhere(target) => {
+                ("points elsewhere", Some(target.display().to_string()))
             }
+            LinkState::DanglingSource => ("dangling source", None),
+            LinkState::NoMatches => ("no matches", None),
+            LinkState::Replaced { is_dir: true } => ("replaced by dir", None),
+            LinkState::Replaced { is_dir: false } => ("replaced by file", None),
+            LinkState::Unreadable(reason) => ("unreadable", Some(reason.clone())),
+        };
+        print!(
+            "{:<17} {} -> {}",
+            state, entry.mapping.dst, entry.mapping.src
+        );
+        match detail {
+            Some(detail) => println!(" ({})", detail),
+            None => println!(),
         }
     }
-    return result;
+    let ok = entries.iter().filter(|e| e.state.is_ok()).count();
+    println!("\n{} ok, {} drifted", ok, entries.len() - ok);
 }
 
-fn make_symlink(
-    src: &PathBuf,
-    dst: &PathBuf,
-    force: bool,
-    _use_junction: bool,
-) -> Result<(), String> {
-    if !src.exists() {
-        return Err(format!(
-            "Source file or directory '{}' does not exist",
-            src.display()
-        ));
+/// Asks a yes or no question on the terminal, answering no if there is none.
+fn confirm(question: &str) -> bool {
+    if !stdin().is_terminal() {
+        return false;
     }
-    let dst_exists: bool;
-    match dst.try_exists() {
-        Ok(result) => {
-            dst_exists = result;
-        },
-        Err(e) => {
-            if !force {
-                return Err(format!(
-                    "Failed to check destination file or directory '{}': {}",
-                    dst.display(),
-                    e
-                ))
-            }
-            dst_exists = true;
+    print!("{} [y/N] ", question);
+    let _ = stdout().flush();
+    let mut answer = String::new();
+    if stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Values with a path separator or naming an existing directory are backup directories,
+/// anything else is a suffix.
+fn parse_backup(value: &str) -> BackupMode {
+    let path = Path::new(value);
+    if value.contains(['/', std::path::MAIN_SEPARATOR]) || path.is_dir() {
+        BackupMode::Dir(absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+    } else {
+        BackupMode::Suffix(value.to_string())
+    }
+}
+
+fn print_backups() -> Result<(), ImplinkError> {
+    let manifest = BackupManifest::load_or_default(&BackupManifest::default_path()?)?;
+    if manifest.backups.is_empty() {
+        println!("There are no backups.");
+    }
+    for entry in &manifest.backups {
+        println!(
+            "{} -> {} (created at {})",
+            entry.original.display(),
+            entry.backup.display(),
+            entry.created
+        );
+    }
+    Ok(())
+}
+
+fn print_mappings(file: &str) -> Result<(), ImplinkError> {
+    let mapping_file = MappingFile::load(Path::new(file))?;
+    if mapping_file.mapping.is_empty() {
+        println!("There are no entries.");
+    }
+    for entry in &mapping_file.mapping {
+        let mut options = Vec::new();
+        if entry.kind == LinkKind::Hard {
+            options.push("hard".to_string());
         }
-    } 
-    if dst_exists {
-        if !force {
-            match remove_dir(dst) {
-                Ok(_) => (),
-                Err(_) => {
-                    return Err(format!(
-                        "Destination file or directory '{}' already exists",
-                        dst.display()
-                    ));
-                }
+        for (set, name) in [
+            (entry.relative, "relative"),
+            (entry.force, "force"),
+            (entry.junction, "junction"),
+        ] {
+            if set {
+                options.push(name.to_string());
             }
-        } else {
-            rm_rf(dst).unwrap();
         }
-    }
-    let result = _make_symlink(src, dst, _use_junction);
-    match result {
-        Ok(_) => (),
-        Err(e) => {
-            if !force {
-                return Err(format!(
-                    "Failed to create symlink '{}': {}",
-                    dst.display(),
-                    e
-                ));
-            }
-            // Workaround :)
-            println!("Error: {}", e);
-            print!("Trying to remove destination file or directory '{}'... ", dst.display());
-            match rm_rf(dst) {
-                Ok(_) => {
-                    println!("OK");
-                },
-                Err(e) => {
-                    println!("FAILED");
-                    println!("Failed to remove destination file or directory '{}': {}", dst.display(), e);
-                    // Return because we can't even remove the destination
-                    return Err(format!(
-                        "Failed to create symlink '{}': {}",
-                        dst.display(),
-                        e
-                    ));
-                }
-            }
-            match _make_symlink(src, dst, _use_junction) {
-                Ok(_) => (),
-                Err(e) => {
-                    return Err(format!(
-                        "Failed to create symlink '{}': {}",
-                        dst.display(),
-                        e
-                    ));
-                }
-            }
+        if !entry.exclude.is_empty() {
+            options.push(format!("exclude {}", entry.exclude.join(", ")));
+        }
+        print!("{} -> {}", entry.dst, entry.src);
+        if options.is_empty() {
+            println!();
+        } else {
+            println!(" ({})", options.join("; "));
         }
     }
-    println!(
-        "Symlinked '{}' to '{}'",
-        src.to_str().unwrap(),
-        dst.to_str().unwrap()
-    );
     Ok(())
 }
 
-fn generate_mapping(
-    src: &PathBuf,
-    dst: &PathBuf,
-    force: bool,
-    use_junction: bool,
-    out_file: &String,
-) {
-    println!("Generating mapping file...");
-    let mapping = Mapping {
-        src: src.to_str().unwrap().to_string(),
-        dst: dst.to_str().unwrap().to_string(),
-        force: force,
-        junction: use_junction,
-    };
-    let mapping_file = MappingFile {
-        mapping: vec![mapping],
-    };
-    let json = serde_json::to_string_pretty(&mapping_file).unwrap();
-    write(out_file, json).unwrap();
-    println!("Mapping file has been written to '{}'.", out_file);
+/// Exit code of `status` when the filesystem doesn't match the mapping file.
+const DRIFT_EXIT_CODE: u8 = 9;
+/// Exit code when quitting right away on a second Ctrl-C, the same as for an interrupted move.
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+fn fail(e: ImplinkError) -> ExitCode {
+    eprintln!("{}", e);
+    ExitCode::from(e.exit_code())
 }
 
-fn restore_mapping(file: &String) {
-    println!("Restoring mapping from file '{}'...", file);
-    let json = read_to_string(file).unwrap();
-    let mapping_file: MappingFile = serde_json::from_str(&json).unwrap();
-    for mapping in mapping_file.mapping {
-        let src = PathBuf::from(mapping.src);
-        let dst = PathBuf::from(mapping.dst);
-        match make_symlink(&src, &dst, mapping.force, mapping.junction) {
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("{}", e);
-                return;
-            }
+/// Exit code for the summary of a report, the one of its first failed entry.
+fn report_exit_code(report: &Report) -> ExitCode {
+    match report.first_error() {
+        Some(e) => ExitCode::from(e.exit_code()),
+        None => ExitCode::SUCCESS,
+    }
+}
+
+/// Parses the command line, rejecting deprecated flags in front of a subcommand, which
+/// would be ignored otherwise.
+fn parse_args() -> Args {
+    let mut command = Args::command();
+    let matches = command.get_matches_mut();
+    if let Some((name, _)) = matches.subcommand() {
+        let legacy = command
+            .get_arguments()
+            .filter(|arg| arg.is_hide_set())
+            .find(|arg| {
+                matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            })
+            .map(|arg| arg.get_long().unwrap_or_default().to_string());
+        if let Some(flag) = legacy {
+            command
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "'--{}' can't be used before the subcommand '{}'",
+                        flag, name
+                    ),
+                )
+                .exit();
         }
     }
-    println!("Mapping has been restored.");
+    Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
 }
 
-fn main() {
-    println!(
-        "implink-rs v{} - https://github.com/teppyboy/implink-rs",
-        env!("CARGO_PKG_VERSION")
-    );
-    let args = Args::parse();
-    if args.restore_mapping.is_some() {
-        restore_mapping(&args.restore_mapping.unwrap());
-        return;
+fn main() -> ExitCode {
+    let args = parse_args();
+    let (dry_run, json) = (args.dry_run, args.json);
+    let command = match args.command {
+        Some(command) => command,
+        None => match args.legacy.into_command() {
+            Some(command) => {
+                let name = match command {
+                    Command::Move { .. } => "move",
+                    Command::Restore { .. } => "restore",
+                    _ => "link",
+                };
+                eprintln!(
+                    "Warning: running implink without a subcommand is deprecated, use 'implink {}' instead",
+                    name
+                );
+                command
+            }
+            None => Args::command()
+                .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+                .exit(),
+        },
+    };
+    if !json {
+        println!(
+            "implink-rs v{} - https://github.com/teppyboy/implink-rs",
+            env!("CARGO_PKG_VERSION")
+        );
     }
-    if args.src.is_none() || args.dst.is_none() {
-        println!("Usage: implink <SRC> <DST>");
-        println!("Execute 'implink --help' for more information.");
-        return;
+    match &command {
+        Command::Status { file } => {
+            return match implink::status(file) {
+                Ok(entries) => {
+                    print_status(&entries);
+                    if entries.iter().all(|e| e.state.is_ok()) {
+                        ExitCode::SUCCESS
+                    } else {
+                        ExitCode::from(DRIFT_EXIT_CODE)
+                    }
+                }
+                Err(e) => fail(e),
+            };
+        }
+        Command::Mapping {
+            command: MappingCommand::Ls { file },
+        } => {
+            return match print_mappings(file) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => fail(e),
+            };
+        }
+        _ => {}
     }
-    let src = absolute(args.src.unwrap()).unwrap();
-    let dst = absolute(args.dst.unwrap()).unwrap();
-    if args.move_and_link {
-        match move_file_or_directory(&src, &dst, args.force) {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("{}", e);
-                return;
+    let interrupt = Arc::new(AtomicBool::new(false));
+    // The flag is only checked while moving, anything else quits on the first Ctrl-C as usual
+    if command.moves_data() {
+        let handler_interrupt = interrupt.clone();
+        let _ = ctrlc::set_handler(move || {
+            if handler_interrupt.swap(true, Ordering::SeqCst) {
+                std::process::exit(INTERRUPTED_EXIT_CODE.into());
             }
+            eprintln!("\nStopping, press Ctrl-C again to quit right away...");
+        });
+    }
+    // Collected instead of printed when the plan is printed as JSON
+    let plan: RefCell<Vec<Action>> = RefCell::new(Vec::new());
+    let on_event = |event: Event| match event {
+        Event::Planned(action) if json => plan.borrow_mut().push(action),
+        event => print_event(event),
+    };
+    let linker = Linker::new()
+        .confirm_adopt(|src, dst| {
+            // A dry run plans the replacement without asking
+            dry_run
+                || confirm(&format!(
+                    "'{}' already exists, replace it with '{}'?",
+                    src.display(),
+                    dst.display()
+                ))
+        })
+        .dry_run(dry_run)
+        .interrupt(interrupt)
+        .on_event(on_event);
+    let result = match command {
+        Command::Link {
+            src,
+            dst,
+            link,
+            adopt,
+            ignore,
+            backup,
+            output,
+        } => {
+            let linker = output.apply(backup.apply(link.apply(linker)));
+            linker
+                .adopt(adopt)
+                .ignore(ignore)
+                .link(src, dst)
+                .map(|_| ExitCode::SUCCESS)
         }
-        match make_symlink(&dst, &src, args.force, args.junction) {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("{}", e);
-                return;
-            }
+        Command::Move {
+            src,
+            dst,
+            link,
+            moving,
+            backup,
+            output,
+        } => {
+            let linker = output.apply(backup.apply(moving.apply(link.apply(linker))));
+            linker.link(src, dst).map(|_| ExitCode::SUCCESS)
         }
-        if !args.generate_mapping.is_none() {
-            generate_mapping(
-                &dst,
-                &src,
-                args.force,
-                args.junction,
-                &args.generate_mapping.unwrap(),
-            );
+        Command::Restore {
+            file,
+            keep_going,
+            atomic,
+            backup,
+        } => {
+            if !json {
+                println!("Restoring mapping from file '{}'...", file);
+            }
+            let linker = backup.apply(linker).atomic(atomic);
+            if keep_going {
+                linker.restore_all(&file).map(|report| {
+                    if !json {
+                        print_report(&report);
+                    }
+                    report_exit_code(&report)
+                })
+            } else {
+                linker.restore(&file).map(|_| {
+                    if !dry_run {
+                        println!("Mapping has been restored.");
+                    }
+                    ExitCode::SUCCESS
+                })
+            }
         }
-    } else {
-        match make_symlink(&src, &dst, args.force, args.junction) {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("{}", e);
-                return;
+        Command::Unlink {
+            link,
+            target,
+            restore_data,
+            prune,
+            mapping,
+        } => {
+            let linker = linker.restore_data(restore_data).prune(prune);
+            match (link, mapping) {
+                (Some(link), mapping) => {
+                    let linker = match mapping {
+                        Some(mapping) => linker.mapping_output(mapping),
+                        None => linker,
+                    };
+                    linker
+                        .unlink(link, target.as_ref().map(Path::new))
+                        .map(|_| ExitCode::SUCCESS)
+                }
+                (None, Some(mapping)) => linker.unlink_all(mapping).map(|report| {
+                    if !json {
+                        print_report(&report);
+                    }
+                    report_exit_code(&report)
+                }),
+                (None, None) => unreachable!("clap requires a link or a mapping file"),
             }
         }
-        if !args.generate_mapping.is_none() {
-            generate_mapping(
-                &src,
-                &dst,
-                args.force,
-                args.junction,
-                &args.generate_mapping.unwrap(),
-            );
+        Command::Stow {
+            package,
+            target,
+            link,
+            ignore,
+            backup,
+            output,
+        } => {
+            let linker = output.apply(backup.apply(link.apply(linker)));
+            linker.ignore(ignore).stow(package, target).map(|report| {
+                if !json {
+                    print_report(&report);
+                }
+                ExitCode::SUCCESS
+            })
+        }
+        Command::Mapping { command } => match command {
+            MappingCommand::Add {
+                file,
+                src,
+                dst,
+                link,
+                portable,
+            } => link
+                .apply(linker)
+                .portable(portable)
+                .mapping_output(file)
+                .add_mapping(src, dst)
+                .map(|_| ExitCode::SUCCESS),
+            MappingCommand::Rm { file, dst } => linker
+                .mapping_output(file)
+                .remove_mapping(dst)
+                .map(|_| ExitCode::SUCCESS),
+            MappingCommand::Ls { .. } => unreachable!("listed before linking"),
+        },
+        Command::RestoreBackup { path, force } => match path {
+            Some(path) => linker
+                .force(force)
+                .restore_backup(path)
+                .map(|_| ExitCode::SUCCESS),
+            None => print_backups().map(|_| ExitCode::SUCCESS),
+        },
+        Command::Status { .. } => unreachable!("checked before linking"),
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&*plan.borrow()).expect("actions are serializable")
+        );
+    }
+    match result {
+        Ok(code) => code,
+        Err(e @ ImplinkError::Interrupted { .. }) => {
+            // Finish the progress line
+            println!();
+            fail(e)
         }
+        Err(e) => fail(e),
     }
 }
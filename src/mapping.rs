@@ -0,0 +1,351 @@
+use crate::error::{ImplinkError, Result};
+use crate::paths::{escape, expand, normalize, resolve_in};
+use glob::{glob, Pattern};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+use std::path::{absolute, Path, PathBuf, MAIN_SEPARATOR};
+use std::str::FromStr;
+
+/// A single link recorded in a mapping file.
+///
+/// `src` and `dst` may use `~` and environment variables such as `$HOME` or
+/// `${XDG_CONFIG_HOME}`, `$$` is a literal `$`. Relative paths are relative to the mapping file's directory.
+///
+/// A `src` with `*`, `?` or `[...]` is a glob pattern standing for one link per match,
+/// see [`Mapping::expand`]. Escaped as `[*]`, `[?]`, `[[]` and `[]]` they are taken
+/// literally, a `src` with nothing but escaped ones is a plain path.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    /// Source file or directory the link points to
+    pub src: String,
+    /// Location of the link
+    pub dst: String,
+    /// Force overwrite of existing destination
+    pub force: bool,
+    /// Use NTFS junction for directories on Windows
+    pub junction: bool,
+    /// Kind of link to create, symlinks in files written before there was a choice
+    #[serde(default)]
+    pub kind: LinkKind,
+    /// Store the path to the source relative to the directory of the link
+    #[serde(default)]
+    pub relative: bool,
+    /// Patterns of matches of a glob `src` to leave out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// What kind of link a mapping entry creates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    /// A symlink, or a junction for directories on Windows with `junction`
+    #[default]
+    Symlink,
+    /// A hard link for files. Directories are mirrored, with every file hard linked
+    Hard,
+}
+
+impl FromStr for LinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "symlink" => Ok(LinkKind::Symlink),
+            "hard" => Ok(LinkKind::Hard),
+            _ => Err(format!(
+                "unknown link kind '{}', expected symlink or hard",
+                s
+            )),
+        }
+    }
+}
+
+impl Mapping {
+    /// Expands variables in `src` and `dst` and resolves relative paths against `base`.
+    pub fn resolve(&self, base: &Path) -> Result<(PathBuf, PathBuf)> {
+        let src = unescape_glob(&self.src).unwrap_or_else(|| self.src.clone());
+        Ok((resolve_in(&src, base)?, resolve_in(&self.dst, base)?))
+    }
+
+    /// Whether `src` is a glob pattern.
+    pub fn is_glob(&self) -> bool {
+        unescape_glob(&self.src).is_none()
+    }
+
+    /// Whether the entry links inside `dst`, because `src` is a glob or `dst` ends with a
+    /// separator.
+    pub fn links_inside(&self) -> bool {
+        self.is_glob() || self.dst.ends_with(['/', MAIN_SEPARATOR])
+    }
+
+    /// Expands the entry into the links it stands for, with absolute paths escaped like in
+    /// a mapping file.
+    ///
+    /// A glob `src` gives one link per match which no `exclude` pattern matches, named
+    /// after the match inside `dst`. Exclude patterns without a separator are matched
+    /// against file names, others against whole paths. A literal `src` gives a single
+    /// link, inside `dst` if `dst` ends with a separator.
+    pub fn expand(&self, base: &Path) -> Result<Vec<Mapping>> {
+        if !self.links_inside() {
+            return Ok(vec![self.clone()]);
+        }
+        let dst = normalize(&resolve_in(&self.dst, base)?);
+        let sources = if self.is_glob() {
+            self.matches(base)?
+        } else {
+            vec![normalize(&self.resolve(base)?.0)]
+        };
+        Ok(sources
+            .into_iter()
+            .map(|src| Mapping {
+                dst: escape(
+                    &dst.join(src.file_name().unwrap_or_default())
+                        .to_string_lossy(),
+                ),
+                src: escape_glob(&escape(&src.to_string_lossy())),
+                exclude: Vec::new(),
+                ..self.clone()
+            })
+            .collect())
+    }
+
+    /// Paths matching the glob `src` and none of the `exclude` patterns, sorted.
+    fn matches(&self, base: &Path) -> Result<Vec<PathBuf>> {
+        let excludes = self
+            .exclude
+            .iter()
+            .map(|exclude| {
+                let name_only = !exclude.contains(['/', MAIN_SEPARATOR]);
+                let pattern = if name_only {
+                    Pattern::new(exclude)
+                } else {
+                    Pattern::new(&pattern_in(exclude, base)?)
+                };
+                let pattern = pattern.map_err(|e| ImplinkError::InvalidPattern {
+                    pattern: exclude.clone(),
+                    source: e,
+                })?;
+                Ok((pattern, name_only))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let excluded = |path: &Path| {
+            excludes.iter().any(|(pattern, name_only)| {
+                if *name_only {
+                    path.file_name()
+                        .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+                } else {
+                    pattern.matches_path(path)
+                }
+            })
+        };
+        let paths =
+            glob(&pattern_in(&self.src, base)?).map_err(|e| ImplinkError::InvalidPattern {
+                pattern: self.src.clone(),
+                source: e,
+            })?;
+        let mut matches = Vec::new();
+        for path in paths {
+            let path = path.map_err(|e| ImplinkError::Io {
+                path: e.path().to_path_buf(),
+                source: e.into(),
+            })?;
+            if !excluded(&path) {
+                matches.push(path);
+            }
+        }
+        Ok(matches)
+    }
+}
+
+/// Escapes the glob characters in a `src`, so it is taken as a plain path.
+pub(crate) fn escape_glob(src: &str) -> String {
+    Pattern::escape(src)
+}
+
+/// `src` with its escaped glob characters put back, `None` if it is a glob pattern.
+fn unescape_glob(src: &str) -> Option<String> {
+    let mut unescaped = String::new();
+    let mut rest = src;
+    while let Some(index) = rest.find(['*', '?', '[']) {
+        unescaped.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let escaped = ["[*]", "[?]", "[[]", "[]]"]
+            .into_iter()
+            .find(|escaped| rest.starts_with(escaped))?;
+        unescaped.push_str(&escaped[1..2]);
+        rest = &rest[escaped.len()..];
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}
+
+/// Expands variables in `pattern` and makes it absolute, without `base` being taken as
+/// a pattern itself.
+fn pattern_in(pattern: &str, base: &Path) -> Result<String> {
+    let expanded = expand(pattern)?;
+    if Path::new(&expanded).is_absolute() {
+        return Ok(expanded);
+    }
+    Ok(Path::new(&Pattern::escape(&base.to_string_lossy()))
+        .join(expanded)
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// A set of links which can be restored in one go.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MappingFile {
+    pub mapping: Vec<Mapping>,
+}
+
+impl MappingFile {
+    /// Reads a mapping file from disk.
+    pub fn load(path: &Path) -> Result<MappingFile> {
+        let json = read_to_string(path).map_err(|e| ImplinkError::MappingIo {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        serde_json::from_str(&json).map_err(|e| ImplinkError::MappingParse {
+            path: path.to_path_buf(),
+            source: e,
+        })
+    }
+
+    /// Reads a mapping file from disk, or returns an empty one if it doesn't exist yet.
+    pub fn load_or_default(path: &Path) -> Result<MappingFile> {
+        match path.try_exists() {
+            Ok(true) => MappingFile::load(path),
+            Ok(false) => Ok(MappingFile::default()),
+            Err(e) => Err(ImplinkError::MappingIo {
+                path: path.to_path_buf(),
+                source: e,
+            }),
+        }
+    }
+
+    /// Directory the relative paths in the mapping file at `path` are resolved against.
+    pub fn base_dir(path: &Path) -> Result<PathBuf> {
+        let path = absolute(path).map_err(|e| ImplinkError::InvalidPath {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        Ok(path.parent().map(Path::to_path_buf).unwrap_or(path))
+    }
+
+    /// Adds a mapping, replacing the entry with the same `dst` in place if there is one.
+    ///
+    /// Destinations are compared after resolving them against `base`.
+    pub fn upsert(&mut self, mapping: Mapping, base: &Path) {
+        let dst = resolve_in(&mapping.dst, base).ok();
+        let same_dst = |m: &&mut Mapping| {
+            m.dst == mapping.dst || (dst.is_some() && resolve_in(&m.dst, base).ok() == dst)
+        };
+        match self.mapping.iter_mut().find(same_dst) {
+            Some(existing) => *existing = mapping,
+            None => self.mapping.push(mapping),
+        }
+    }
+
+    /// Removes the entry whose `dst` resolves to `dst`, returning it if there was one.
+    pub fn remove(&mut self, dst: &Path, base: &Path) -> Option<Mapping> {
+        let index = self
+            .mapping
+            .iter()
+            .position(|m| resolve_in(&m.dst, base).is_ok_and(|d| d == dst))?;
+        Some(self.mapping.remove(index))
+    }
+
+    /// Writes the mapping file to disk, overwriting any existing file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| ImplinkError::MappingParse {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        write(path, json).map_err(|e| ImplinkError::MappingIo {
+            path: path.to_path_buf(),
+            source: e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    fn entry(src: &str, dst: &str, exclude: &[&str]) -> Mapping {
+        Mapping {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            force: false,
+            junction: false,
+            kind: LinkKind::Symlink,
+            relative: false,
+            exclude: exclude.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn escaped_sources_are_literal() {
+        assert!(entry("fonts/*.ttf", "out/", &[]).is_glob());
+        assert!(entry("a[12]", "out/", &[]).is_glob());
+        assert!(entry("a[[]1[]]*", "out/", &[]).is_glob());
+        for src in ["cfg[1]", "what?", "a*b", "plain"] {
+            let mapping = entry(&escape_glob(src), "out", &[]);
+            assert!(!mapping.is_glob());
+            assert_eq!(
+                mapping.resolve(Path::new("/base")).unwrap().0,
+                Path::new("/base").join(src)
+            );
+        }
+    }
+
+    #[test]
+    fn expands_globs_without_excluded_matches() {
+        let tmp = tempdir().unwrap();
+        let base = tmp.path();
+        create_dir_all(base.join("fonts/sub")).unwrap();
+        for name in ["a.ttf", "b.ttf", "test-c.ttf", "d.otf", "sub/e.ttf"] {
+            write(base.join("fonts").join(name), "").unwrap();
+        }
+        let expanded = entry("fonts/*.ttf", "out/", &["test-*"])
+            .expand(base)
+            .unwrap();
+        let names: Vec<_> = expanded.iter().map(|m| m.src.clone()).collect();
+        let expected: Vec<_> = ["a.ttf", "b.ttf"]
+            .iter()
+            .map(|name| escape_glob(&escape(&base.join("fonts").join(name).to_string_lossy())))
+            .collect();
+        assert_eq!(names, expected);
+        assert_eq!(expanded[0].resolve(base).unwrap().1, base.join("out/a.ttf"));
+        assert!(expanded
+            .iter()
+            .all(|m| !m.is_glob() && m.exclude.is_empty()));
+
+        // Excludes with a separator are matched against whole paths
+        let expanded = entry("fonts/**/*.ttf", "out/", &["fonts/sub/*"])
+            .expand(base)
+            .unwrap();
+        assert_eq!(expanded.len(), 3);
+        assert!(entry("fonts/*.woff", "out/", &[])
+            .expand(base)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn expands_literal_sources_with_glob_characters() {
+        let tmp = tempdir().unwrap();
+        let base = tmp.path();
+        create_dir_all(base.join("src/cfg[1]")).unwrap();
+        let mapping = entry(&escape_glob("src/cfg[1]"), "home/", &[]);
+        let expanded = mapping.expand(base).unwrap();
+        assert_eq!(expanded.len(), 1);
+        assert_eq!(
+            expanded[0].resolve(base).unwrap(),
+            (base.join("src/cfg[1]"), base.join("home/cfg[1]"))
+        );
+    }
+}
@@ -0,0 +1,758 @@
+use crate::ignores::{is_ignore_file, Ignore};
+use filetime::FileTime;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{
+    create_dir, read_dir, read_link, remove_dir, remove_file, File, Metadata, OpenOptions,
+};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Size of the buffer used when copying file contents.
+pub(crate) const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Metadata kept when files have to be copied to another filesystem.
+///
+/// Renames always keep everything. Ownership and extended attributes which the
+/// user isn't allowed to set are silently dropped. Defaults to everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preserve {
+    /// Permission bits (only the read-only flag on Windows)
+    pub mode: bool,
+    /// Access and modification times
+    pub timestamps: bool,
+    /// Owner and group, on Unix
+    pub ownership: bool,
+    /// Extended attributes, on Unix
+    pub xattr: bool,
+}
+
+impl Preserve {
+    /// Keeps nothing beyond the file contents.
+    pub const NONE: Preserve = Preserve {
+        mode: false,
+        timestamps: false,
+        ownership: false,
+        xattr: false,
+    };
+    /// Keeps all the metadata the user is allowed to.
+    pub const ALL: Preserve = Preserve {
+        mode: true,
+        timestamps: true,
+        ownership: true,
+        xattr: true,
+    };
+}
+
+impl Default for Preserve {
+    fn default() -> Self {
+        Preserve::ALL
+    }
+}
+
+impl FromStr for Preserve {
+    type Err = String;
+
+    /// Parses a comma separated list of `mode`, `timestamps`, `ownership` and `xattr`,
+    /// or `all` / `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut preserve = Preserve::NONE;
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item {
+                "mode" => preserve.mode = true,
+                "timestamps" => preserve.timestamps = true,
+                "ownership" => preserve.ownership = true,
+                "xattr" => preserve.xattr = true,
+                "all" => preserve = Preserve::ALL,
+                "none" => (),
+                _ => {
+                    return Err(format!(
+                        "unknown attribute '{}', expected mode, timestamps, ownership, xattr, all or none",
+                        item
+                    ))
+                }
+            }
+        }
+        Ok(preserve)
+    }
+}
+
+/// Whether a rename failed because source and destination are on different filesystems.
+pub(crate) fn is_cross_device(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::CrossesDevices
+}
+
+/// Whether `src` and `dst` (or its closest existing parent) are on the same filesystem.
+///
+/// Returns `None` when that can't be determined, like on Windows.
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn same_device(src: &Path, dst: &Path) -> Option<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let src_device = src.symlink_metadata().ok()?.dev();
+    let existing = dst.ancestors().find(|p| p.symlink_metadata().is_ok())?;
+    Some(existing.metadata().ok()?.dev() == src_device)
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn same_device(_: &Path, _: &Path) -> Option<bool> {
+    None
+}
+
+/// Ignores failures caused by the user not being allowed to set some metadata, or the
+/// destination filesystem not supporting it.
+fn allowed(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::PermissionDenied | ErrorKind::Unsupported
+            ) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn copy_ownership(metadata: &Metadata, dst: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{lchown, MetadataExt};
+    allowed(lchown(dst, Some(metadata.uid()), Some(metadata.gid())))
+}
+
+#[cfg(target_os = "windows")]
+fn copy_ownership(_: &Metadata, _: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    let names = match xattr::list(src) {
+        Ok(names) => names,
+        Err(e) => return allowed(Err(e)),
+    };
+    for name in names {
+        match xattr::get(src, &name) {
+            Ok(Some(value)) => allowed(xattr::set(dst, &name, &value))?,
+            Ok(None) => (),
+            Err(e) => allowed(Err(e))?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn copy_xattrs(_: &Path, _: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Copies the metadata selected by `preserve` from `src` (described by `metadata`) to `dst`.
+///
+/// Links are never followed.
+pub(crate) fn copy_metadata(
+    src: &Path,
+    metadata: &Metadata,
+    dst: &Path,
+    preserve: &Preserve,
+) -> io::Result<()> {
+    // Changing the owner clears the setuid and setgid bits, so it goes before the mode
+    if preserve.ownership {
+        copy_ownership(metadata, dst)?;
+    }
+    if preserve.xattr {
+        copy_xattrs(src, dst)?;
+    }
+    if preserve.mode && !metadata.file_type().is_symlink() {
+        std::fs::set_permissions(dst, metadata.permissions())?;
+    }
+    // Last, as everything else may update the times
+    if preserve.timestamps {
+        filetime::set_symlink_file_times(
+            dst,
+            FileTime::from_last_access_time(metadata),
+            FileTime::from_last_modification_time(metadata),
+        )?;
+    }
+    Ok(())
+}
+
+/// How files are copied when they can't be renamed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CopyOptions {
+    pub(crate) preserve: Preserve,
+    /// Hash every copy and compare it to the source before the source is removed
+    pub(crate) verify: bool,
+    /// Stop copying once this is set, leaving the file being copied in the source
+    pub(crate) interrupt: Option<Arc<AtomicBool>>,
+}
+
+impl CopyOptions {
+    fn interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|interrupt| interrupt.load(Ordering::SeqCst))
+    }
+}
+
+/// What had been moved when a move was interrupted, carried by the returned error.
+#[derive(Debug)]
+pub(crate) struct Interrupted {
+    pub(crate) files: u64,
+    pub(crate) bytes: u64,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "interrupted after moving {} files ({} bytes)",
+            self.files, self.bytes
+        )
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+fn interrupted(files: u64, bytes: u64) -> io::Error {
+    io::Error::new(ErrorKind::Interrupted, Interrupted { files, bytes })
+}
+
+/// What had been moved, if `e` is caused by an interruption.
+pub(crate) fn interruption(e: &io::Error) -> Option<&Interrupted> {
+    e.get_ref()?.downcast_ref()
+}
+
+/// BLAKE3 hash of the contents of a file.
+pub(crate) fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize())
+}
+
+/// Copies `src` to `dst` in chunks, calling `progress` with the copied and total bytes.
+///
+/// The copy is synced to disk and its size checked against the source before returning.
+/// With `verify`, the copy is also read back and its hash compared to the one of the
+/// source data, which is returned. A partially written or mismatching `dst` is removed
+/// on failure, including when the copy is interrupted.
+pub(crate) fn copy_file_streamed(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<Option<blake3::Hash>> {
+    let mut copy = || -> io::Result<Option<blake3::Hash>> {
+        let mut reader = File::open(src)?;
+        let total = reader.metadata()?.len();
+        let mut writer = File::create(dst)?;
+        let mut hasher = options.verify.then(blake3::Hasher::new);
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut copied = 0;
+        loop {
+            if options.interrupted() {
+                return Err(interrupted(0, 0));
+            }
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            writer.write_all(&buffer[..read])?;
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&buffer[..read]);
+            }
+            copied += read as u64;
+            progress(copied, total);
+        }
+        writer.sync_all()?;
+        let written = writer.metadata()?.len();
+        if written != total {
+            return Err(io::Error::other(format!(
+                "copied {} bytes but the source has {}",
+                written, total
+            )));
+        }
+        let Some(hasher) = hasher else {
+            return Ok(None);
+        };
+        let expected = hasher.finalize();
+        if hash_file(dst)? != expected {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the copy at '{}' doesn't match the source '{}'",
+                    dst.display(),
+                    src.display()
+                ),
+            ));
+        }
+        Ok(Some(expected))
+    };
+    copy().inspect_err(|_| {
+        let _ = remove_file(dst);
+    })
+}
+
+/// Copies a file and its metadata.
+fn copy_with_metadata(
+    src: &Path,
+    metadata: &Metadata,
+    dst: &Path,
+    options: &CopyOptions,
+    progress: impl FnMut(u64, u64),
+) -> io::Result<Option<blake3::Hash>> {
+    let hash = copy_file_streamed(src, dst, options, progress)?;
+    copy_metadata(src, metadata, dst, &options.preserve).inspect_err(|_| {
+        let _ = remove_file(dst);
+    })?;
+    Ok(hash)
+}
+
+/// Moves a single file, copying it and removing the source when a rename isn't possible
+/// because `dst` is on another filesystem.
+///
+/// Returns the hash of the copy if it has been verified.
+pub(crate) fn move_file(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+    progress: impl FnMut(u64, u64),
+) -> io::Result<Option<blake3::Hash>> {
+    match std::fs::rename(src, dst) {
+        Ok(_) => Ok(None),
+        Err(e) if is_cross_device(&e) => {
+            let metadata = src.symlink_metadata()?;
+            let hash = copy_with_metadata(src, &metadata, dst, options, progress)?;
+            remove_file(src)?;
+            Ok(hash)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Rules for the entries of `dir`, for walks which fail with I/O errors.
+fn enter(ignore: &Ignore, dir: &Path) -> io::Result<Ignore> {
+    ignore.child(dir).map_err(io::Error::other)
+}
+
+/// Total size of the files under `path` which aren't ignored, without following links.
+pub(crate) fn tree_size(path: &Path, ignore: &Ignore) -> io::Result<u64> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        return Ok(0);
+    }
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let ignore = enter(ignore, path)?;
+    let mut size = 0;
+    for entry in read_dir(path)? {
+        let path = entry?.path();
+        if !ignore.is_ignored(&path, path.symlink_metadata()?.is_dir()) {
+            size += tree_size(&path, &ignore)?;
+        }
+    }
+    Ok(size)
+}
+
+/// Recreates the link at `src` as `dst`, pointing to the same target.
+fn copy_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    let target = read_link(src)?;
+    #[cfg(not(target_os = "windows"))]
+    return std::os::unix::fs::symlink(target, dst);
+    #[cfg(target_os = "windows")]
+    return if src.is_dir() {
+        std::os::windows::fs::symlink_dir(target, dst)
+    } else {
+        std::os::windows::fs::symlink_file(target, dst)
+    };
+}
+
+/// Record of the entries a copying directory move has finished, kept next to the
+/// destination as `.<name>.implink-move` so an interrupted move can be resumed.
+///
+/// The first line holds the source, every following line a finished path relative to it,
+/// all as JSON strings. A line torn by a crash is ignored, which only means that entry
+/// is copied again.
+pub(crate) struct MoveJournal {
+    file: File,
+    done: HashSet<PathBuf>,
+}
+
+impl MoveJournal {
+    /// Where the journal of a move to `dst` is kept.
+    pub(crate) fn path(dst: &Path) -> PathBuf {
+        let name = dst.file_name().unwrap_or_default().to_string_lossy();
+        dst.with_file_name(format!(".{}.implink-move", name))
+    }
+
+    fn read(path: &Path) -> io::Result<(PathBuf, HashSet<PathBuf>)> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut lines = contents
+            .lines()
+            .map(|line| serde_json::from_str::<String>(line).ok());
+        let Some(Some(src)) = lines.next() else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "the move journal has no source",
+            ));
+        };
+        Ok((src.into(), lines.flatten().map(PathBuf::from).collect()))
+    }
+
+    /// Whether a move from `src` to `dst` has been interrupted and can be resumed.
+    pub(crate) fn exists(src: &Path, dst: &Path) -> bool {
+        Self::read(&Self::path(dst)).is_ok_and(|(source, _)| source == src)
+    }
+
+    /// Opens the journal of a move from `src` to `dst`, continuing the one left by an
+    /// interrupted move if there is one.
+    pub(crate) fn open(src: &Path, dst: &Path) -> io::Result<MoveJournal> {
+        let path = Self::path(dst);
+        let done = match Self::read(&path) {
+            Ok((source, done)) if source == src => {
+                let mut file = OpenOptions::new().append(true).open(&path)?;
+                // Start a new line after a torn one
+                file.write_all(b"\n")?;
+                return Ok(MoveJournal { file, done });
+            }
+            _ => HashSet::new(),
+        };
+        let mut file = File::create(&path)?;
+        writeln!(file, "{}", json_string(src))?;
+        file.sync_data()?;
+        Ok(MoveJournal { file, done })
+    }
+
+    fn is_done(&self, relative: &Path) -> bool {
+        self.done.contains(relative)
+    }
+
+    /// Records that `relative` has been copied, before its source is removed.
+    fn mark_done(&mut self, relative: &Path) -> io::Result<()> {
+        writeln!(self.file, "{}", json_string(relative))?;
+        self.file.sync_data()
+    }
+
+    /// Removes the journal of a move to `dst` once the move is complete.
+    pub(crate) fn remove(dst: &Path) -> io::Result<()> {
+        match remove_file(Self::path(dst)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Paths which aren't valid UTF-8 are stored lossily, so they are never found done and
+/// are always copied again.
+fn json_string(path: &Path) -> String {
+    serde_json::to_string(&path.to_string_lossy()).expect("strings are serializable")
+}
+
+/// Moves everything inside `src` into the existing directory `dst`, renaming entries
+/// where possible and copying them when they are on different filesystems. `src` itself
+/// is left behind empty, its metadata is copied to `dst`.
+///
+/// Every entry is recorded in `journal` once it has been copied and is then removed from
+/// `src`, so a failure leaves each file either fully in `src` or fully in `dst` and the
+/// move can be resumed with the same journal. `progress` is called with the file being
+/// copied and the bytes copied so far out of the total. Returns the hashes of the
+/// verified copies.
+pub(crate) fn move_tree(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+    journal: &mut MoveJournal,
+    ignore: &Ignore,
+    mut progress: impl FnMut(&Path, u64, u64),
+) -> io::Result<Vec<(PathBuf, blake3::Hash)>> {
+    let metadata = src.symlink_metadata()?;
+    let total = tree_size(src, ignore)?;
+    let mut tree = TreeMove {
+        root: src,
+        options,
+        journal,
+        rename: same_device(src, dst) != Some(false),
+        progress: &mut |file, copied| progress(file, copied, total),
+        files: 0,
+        copied: 0,
+        hashes: Vec::new(),
+    };
+    if let Err(e) = tree.move_entries(src, dst, ignore) {
+        return Err(match interruption(&e) {
+            Some(_) => interrupted(tree.files, tree.copied),
+            None => e,
+        });
+    }
+    copy_metadata(src, &metadata, dst, &options.preserve)?;
+    Ok(tree.hashes)
+}
+
+/// State of a [`move_tree`] call.
+struct TreeMove<'a> {
+    root: &'a Path,
+    options: &'a CopyOptions,
+    journal: &'a mut MoveJournal,
+    /// Whether entries may still be renamed instead of copied
+    rename: bool,
+    progress: &'a mut dyn FnMut(&Path, u64),
+    files: u64,
+    copied: u64,
+    hashes: Vec<(PathBuf, blake3::Hash)>,
+}
+
+impl TreeMove<'_> {
+    /// Returns whether anything ignored has been left in `src`.
+    fn move_entries(&mut self, src: &Path, dst: &Path, ignore: &Ignore) -> io::Result<bool> {
+        let ignore = enter(ignore, src)?;
+        let mut kept = false;
+        for entry in read_dir(src)? {
+            if self.options.interrupted() {
+                return Err(interrupted(self.files, self.copied));
+            }
+            let path = entry?.path();
+            let target = dst.join(path.file_name().unwrap_or_default());
+            let metadata = path.symlink_metadata()?;
+            if ignore.is_ignored(&path, metadata.is_dir()) {
+                kept = true;
+                continue;
+            }
+            // Directories with ignored entries are moved entry by entry
+            let whole =
+                !metadata.is_dir() || !ignore.ignores_any(&path).map_err(io::Error::other)?;
+            if self.rename && whole && target.symlink_metadata().is_err() {
+                match std::fs::rename(&path, &target) {
+                    Ok(_) => {
+                        self.files += 1;
+                        continue;
+                    }
+                    Err(e) if is_cross_device(&e) => self.rename = false,
+                    Err(e) => return Err(e),
+                }
+            }
+            if metadata.is_dir() {
+                match create_dir(&target) {
+                    Err(e) if e.kind() != ErrorKind::AlreadyExists || !target.is_dir() => {
+                        return Err(e)
+                    }
+                    _ => (),
+                }
+                let kept_inside = self.move_entries(&path, &target, &ignore)?;
+                // After the contents, so a read-only mode or the times aren't disturbed by them
+                copy_metadata(&path, &metadata, &target, &self.options.preserve)?;
+                if kept_inside {
+                    kept = true;
+                } else {
+                    remove_dir(&path)?;
+                }
+                continue;
+            }
+            let relative = path.strip_prefix(self.root).unwrap_or(&path).to_path_buf();
+            if !self.journal.is_done(&relative) {
+                // Left over from an interrupted copy
+                if target.symlink_metadata().is_ok() {
+                    remove_file(&target)?;
+                }
+                if metadata.file_type().is_symlink() {
+                    copy_symlink(&path, &target)?;
+                    copy_metadata(&path, &metadata, &target, &self.options.preserve)?;
+                } else {
+                    let before = self.copied;
+                    let progress = &mut self.progress;
+                    let hash = copy_with_metadata(
+                        &path,
+                        &metadata,
+                        &target,
+                        self.options,
+                        |copied, _| progress(&path, before + copied),
+                    )?;
+                    self.hashes.extend(hash.map(|hash| (target, hash)));
+                }
+                self.journal.mark_done(&relative)?;
+            }
+            if !metadata.file_type().is_symlink() {
+                self.copied += metadata.len();
+            }
+            remove_file(&path).or_else(|_| remove_dir(&path))?;
+            self.files += 1;
+        }
+        Ok(kept)
+    }
+}
+
+/// Hashes every file under `path`, without following links.
+pub(crate) fn hash_tree(path: &Path) -> io::Result<Vec<(PathBuf, blake3::Hash)>> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        return Ok(Vec::new());
+    }
+    if !metadata.is_dir() {
+        return Ok(vec![(path.to_path_buf(), hash_file(path)?)]);
+    }
+    let mut hashes = Vec::new();
+    for entry in read_dir(path)? {
+        hashes.extend(hash_tree(&entry?.path())?);
+    }
+    Ok(hashes)
+}
+
+/// Whether `path` is left out of a mirrored tree, like ignore files themselves.
+fn left_out(ignore: &Ignore, path: &Path) -> io::Result<bool> {
+    Ok(is_ignore_file(path) || ignore.is_ignored(path, path.symlink_metadata()?.is_dir()))
+}
+
+/// Hard links `dst` to the file `src`, or mirrors the directory `src` at `dst` with
+/// every file in it which isn't ignored hard linked. Links inside `src` are hard linked
+/// themselves.
+pub(crate) fn hard_link_tree(src: &Path, dst: &Path, ignore: &Ignore) -> io::Result<()> {
+    let metadata = src.symlink_metadata()?;
+    if !metadata.is_dir() {
+        return std::fs::hard_link(src, dst);
+    }
+    create_dir(dst)?;
+    let ignore = enter(ignore, src)?;
+    for entry in read_dir(src)? {
+        let path = entry?.path();
+        if !left_out(&ignore, &path)? {
+            hard_link_tree(
+                &path,
+                &dst.join(path.file_name().unwrap_or_default()),
+                &ignore,
+            )?;
+        }
+    }
+    copy_metadata(src, &metadata, dst, &Preserve::ALL)
+}
+
+/// Whether `dst` is a hard link to the file `src`, or mirrors the directory `src` with
+/// hard links to every file in it which isn't ignored. Files only at `dst` don't matter.
+pub(crate) fn is_hard_linked(src: &Path, dst: &Path, ignore: &Ignore) -> bool {
+    let (Ok(src_metadata), Ok(dst_metadata)) = (src.symlink_metadata(), dst.symlink_metadata())
+    else {
+        return false;
+    };
+    if src_metadata.file_type().is_symlink() {
+        // Comparing the files they point to would fail for dangling links
+        return dst_metadata.file_type().is_symlink()
+            && read_link(src)
+                .ok()
+                .is_some_and(|target| read_link(dst).ok() == Some(target));
+    }
+    if !src_metadata.is_dir() {
+        return !dst_metadata.is_dir() && same_file::is_same_file(src, dst).unwrap_or(false);
+    }
+    if !dst_metadata.is_dir() {
+        return false;
+    }
+    let (Ok(ignore), Ok(mut entries)) = (ignore.child(src), read_dir(src)) else {
+        return false;
+    };
+    entries.all(|entry| {
+        entry.is_ok_and(|entry| {
+            let path = entry.path();
+            left_out(&ignore, &path).unwrap_or(false)
+                || is_hard_linked(
+                    &path,
+                    &dst.join(path.file_name().unwrap_or_default()),
+                    &ignore,
+                )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn journal_resumes_the_same_move() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        let mut journal = MoveJournal::open(&src, &dst).unwrap();
+        journal.mark_done(Path::new("a")).unwrap();
+        journal.mark_done(Path::new("sub/b")).unwrap();
+        drop(journal);
+        assert!(MoveJournal::exists(&src, &dst));
+
+        let journal = MoveJournal::open(&src, &dst).unwrap();
+        assert!(journal.is_done(Path::new("a")));
+        assert!(journal.is_done(Path::new("sub/b")));
+        assert!(!journal.is_done(Path::new("c")));
+
+        // A move from elsewhere to the same destination starts over
+        assert!(!MoveJournal::exists(&dir.join("other"), &dst));
+        let journal = MoveJournal::open(&dir.join("other"), &dst).unwrap();
+        assert!(!journal.is_done(Path::new("a")));
+        MoveJournal::remove(&dst).unwrap();
+        assert!(!MoveJournal::exists(&src, &dst));
+    }
+
+    #[test]
+    fn journal_skips_torn_lines() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        let path = MoveJournal::path(&dst);
+        write(&path, format!("{}\n\"a\"\n\"sub/b", json_string(&src))).unwrap();
+        let mut journal = MoveJournal::open(&src, &dst).unwrap();
+        assert!(journal.is_done(Path::new("a")));
+        assert!(!journal.is_done(Path::new("sub/b")));
+
+        // Entries recorded after resuming start on a line of their own
+        journal.mark_done(Path::new("c")).unwrap();
+        let (_, done) = MoveJournal::read(&path).unwrap();
+        assert!(done.contains(Path::new("c")));
+    }
+
+    #[test]
+    fn move_tree_resumes_from_journal() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        create_dir_all(src.join("sub")).unwrap();
+        create_dir_all(&dst).unwrap();
+        write(src.join("a"), "a").unwrap();
+        write(src.join("c"), "c").unwrap();
+        write(src.join("sub/b"), "b").unwrap();
+        // `a` has been copied before the interruption, `c` only partly
+        write(dst.join("a"), "copied").unwrap();
+        write(dst.join("c"), "partial").unwrap();
+        let mut journal = MoveJournal::open(&src, &dst).unwrap();
+        journal.mark_done(Path::new("a")).unwrap();
+        drop(journal);
+
+        let mut journal = MoveJournal::open(&src, &dst).unwrap();
+        let options = CopyOptions::default();
+        move_tree(
+            &src,
+            &dst,
+            &options,
+            &mut journal,
+            &Ignore::default(),
+            |_, _, _| (),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(dst.join("a")).unwrap(), "copied");
+        assert_eq!(std::fs::read_to_string(dst.join("c")).unwrap(), "c");
+        assert_eq!(std::fs::read_to_string(dst.join("sub/b")).unwrap(), "b");
+        assert_eq!(read_dir(&src).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn journal_without_source_is_invalid() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("journal");
+        write(&path, "").unwrap();
+        assert!(MoveJournal::read(&path).is_err());
+        write(&path, "\"torn").unwrap();
+        assert!(MoveJournal::read(&path).is_err());
+    }
+}
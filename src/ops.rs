@@ -0,0 +1,1051 @@
+use crate::backup::{timestamp, BackupEntry, BackupMode};
+use crate::error::{ImplinkError, Result};
+use crate::ignores::Ignore;
+use crate::linker::Event;
+use crate::mapping::LinkKind;
+use crate::mover::{self, CopyOptions, MoveJournal, Preserve};
+use crate::paths::{normalize, relative_to};
+use crate::plan::Action;
+use crate::preflight;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env::home_dir;
+use std::fs::{
+    create_dir, create_dir_all, read_link, remove_dir, remove_dir_all, remove_file, rename,
+    symlink_metadata,
+};
+use std::io;
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::fs::symlink;
+#[cfg(target_os = "windows")]
+use std::os::windows::fs::{symlink_dir, symlink_file};
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "windows")]
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Actual symlink implementation for Windows
+///
+/// `target` is what the link stores, either `src` itself or a path relative to the link.
+/// Junctions always store `src`.
+#[cfg(target_os = "windows")]
+fn _make_symlink(src: &Path, target: &Path, dst: &Path, use_junction: bool) -> io::Result<()> {
+    if src.is_dir() {
+        if use_junction {
+            return junction::create(src, dst);
+        }
+        return symlink_dir(target, dst);
+    }
+    symlink_file(target, dst)
+}
+
+/// Actual symlink implementation for other platforms
+#[cfg(not(target_os = "windows"))]
+fn _make_symlink(_: &Path, target: &Path, dst: &Path, _: bool) -> io::Result<()> {
+    symlink(target, dst)
+}
+
+/// Reads the target of a link, resolving relative targets against the link's directory.
+///
+/// The result is normalized, so compare it to normalized paths.
+pub(crate) fn read_link_absolute(link: &Path) -> io::Result<PathBuf> {
+    let target = read_link(link)?;
+    Ok(normalize(&match link.parent() {
+        Some(parent) if target.is_relative() => parent.join(target),
+        _ => target,
+    }))
+}
+
+/// What a dry run assumes a path looks like after the actions planned so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Simulated {
+    Missing,
+    EmptyDir,
+    File,
+    Dir,
+}
+
+/// How to undo a change made while journaling.
+#[derive(Debug)]
+enum Undo {
+    /// Remove a link which has been created
+    RemoveLink(PathBuf),
+    /// Remove a hard link or a mirrored tree of them which has been created
+    RemoveHardLink(PathBuf),
+    /// Put back a destination which has been moved aside instead of being removed
+    Unstash { stash: PathBuf, original: PathBuf },
+    /// Recreate an empty directory which has been removed
+    CreateDir(PathBuf),
+    /// Remove a directory which has been created
+    RemoveDir(PathBuf),
+    /// Move a backup back, keeping it on commit
+    Unbackup { backup: PathBuf, original: PathBuf },
+}
+
+/// Removes a file, directory tree or link without following links.
+fn remove_any(path: &Path) -> io::Result<()> {
+    if symlink_metadata(path)?.is_dir() {
+        remove_dir_all(path)
+    } else {
+        // Directory symlinks and junctions on Windows have to be removed as directories
+        remove_file(path).or_else(|_| remove_dir(path))
+    }
+}
+
+/// Performs filesystem changes, or only reports them as [`Action`]s in a dry run.
+///
+/// In a dry run, every planned change is remembered so later decisions (does the
+/// destination still exist, is it empty, ...) see the same state a real run would.
+///
+/// With journaling, destinations are moved aside instead of being removed, so every
+/// change can be undone with [`Ops::rollback`] until [`Ops::commit`] is called.
+pub(crate) struct Ops<'a> {
+    report: &'a dyn Fn(Event),
+    dry_run: bool,
+    simulated: RefCell<HashMap<PathBuf, Simulated>>,
+    journal: Option<RefCell<Vec<Undo>>>,
+    backup: Option<BackupMode>,
+    backups: RefCell<Vec<BackupEntry>>,
+    copy: CopyOptions,
+    checksums: RefCell<Vec<(PathBuf, blake3::Hash)>>,
+    preflight: bool,
+    ignore: Vec<String>,
+}
+
+impl<'a> Ops<'a> {
+    pub(crate) fn new(report: &'a dyn Fn(Event), dry_run: bool) -> Self {
+        Ops {
+            report,
+            dry_run,
+            simulated: RefCell::new(HashMap::new()),
+            journal: None,
+            backup: None,
+            backups: RefCell::new(Vec::new()),
+            copy: CopyOptions::default(),
+            checksums: RefCell::new(Vec::new()),
+            preflight: true,
+            ignore: Vec::new(),
+        }
+    }
+
+    /// Backs up destinations replaced with `force` instead of removing them.
+    pub(crate) fn with_backup(mut self, backup: Option<BackupMode>) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Metadata to keep when files have to be copied to another filesystem.
+    pub(crate) fn with_preserve(mut self, preserve: Preserve) -> Self {
+        self.copy.preserve = preserve;
+        self
+    }
+
+    /// Compares every copied file to its source before the source is removed.
+    pub(crate) fn with_verify(mut self, verify: bool) -> Self {
+        self.copy.verify = verify;
+        self
+    }
+
+    /// Stops moving files once `interrupt` is set.
+    pub(crate) fn with_interrupt(mut self, interrupt: Option<Arc<AtomicBool>>) -> Self {
+        self.copy.interrupt = interrupt;
+        self
+    }
+
+    /// Checks that sources can be moved before moving them.
+    pub(crate) fn with_preflight(mut self, preflight: bool) -> Self {
+        self.preflight = preflight;
+        self
+    }
+
+    /// Extra gitignore-style patterns for the trees which are moved or mirrored.
+    pub(crate) fn with_ignore(mut self, ignore: Vec<String>) -> Self {
+        self.ignore = ignore;
+        self
+    }
+
+    /// Hashes of the files verified so far, by their new path.
+    pub(crate) fn take_checksums(&self) -> Vec<(PathBuf, blake3::Hash)> {
+        self.checksums.take()
+    }
+
+    /// Backups made so far, to be added to the backup manifest.
+    pub(crate) fn take_backups(&self) -> Vec<BackupEntry> {
+        self.backups.take()
+    }
+
+    /// Records every change so it can be rolled back.
+    pub(crate) fn journaled(mut self) -> Self {
+        if !self.dry_run {
+            self.journal = Some(RefCell::new(Vec::new()));
+        }
+        self
+    }
+
+    fn record(&self, undo: Undo) {
+        if let Some(journal) = &self.journal {
+            journal.borrow_mut().push(undo);
+        }
+    }
+
+    /// Moves `path` next to itself so it can be put back on rollback.
+    fn stash(&self, path: &Path) -> io::Result<()> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut index = 0;
+        let stash = loop {
+            let stash = path.with_file_name(format!(
+                ".{}.implink-rollback-{}-{}",
+                name,
+                std::process::id(),
+                index
+            ));
+            if symlink_metadata(&stash).is_err() {
+                break stash;
+            }
+            index += 1;
+        };
+        rename(path, &stash)?;
+        self.record(Undo::Unstash {
+            stash,
+            original: path.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Deletes everything moved aside while journaling, making the changes permanent.
+    pub(crate) fn commit(&self) -> Result<()> {
+        let Some(journal) = &self.journal else {
+            return Ok(());
+        };
+        for undo in journal.borrow_mut().drain(..) {
+            if let Undo::Unstash { stash, .. } = undo {
+                remove_any(&stash).map_err(|e| ImplinkError::RemoveFailed {
+                    path: stash,
+                    source: e,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes every journaled change in reverse order.
+    ///
+    /// Steps which fail are reported and skipped so as much as possible is restored.
+    pub(crate) fn rollback(&self) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let mut journal = journal.borrow_mut();
+        while let Some(undo) = journal.pop() {
+            let (path, result) = match undo {
+                Undo::RemoveLink(link) => {
+                    let result = remove_file(&link).or_else(|_| remove_dir(&link));
+                    (link, result)
+                }
+                Undo::RemoveHardLink(link) => {
+                    let result = remove_any(&link);
+                    (link, result)
+                }
+                Undo::Unstash { stash, original } => {
+                    let result = rename(&stash, &original);
+                    (original, result)
+                }
+                Undo::CreateDir(dir) => {
+                    let result = create_dir(&dir);
+                    (dir, result)
+                }
+                Undo::RemoveDir(dir) => {
+                    let result = remove_dir(&dir);
+                    (dir, result)
+                }
+                Undo::Unbackup { backup, original } => {
+                    let result = rename(&backup, &original);
+                    if result.is_ok() {
+                        self.backups.borrow_mut().retain(|b| b.backup != backup);
+                    }
+                    (original, result)
+                }
+            };
+            match result {
+                Ok(_) => self.report(Event::RolledBack { path }),
+                Err(e) => self.report(Event::RollbackFailed {
+                    path,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    pub(crate) fn report(&self, event: Event) {
+        (self.report)(event)
+    }
+
+    /// Reports the action in a dry run, returns whether it should actually be performed.
+    pub(crate) fn perform(&self, action: Action) -> bool {
+        if self.dry_run {
+            self.report(Event::Planned(action));
+        }
+        !self.dry_run
+    }
+
+    fn simulate(&self, path: &Path, state: Simulated) {
+        if self.dry_run {
+            self.simulated
+                .borrow_mut()
+                .insert(path.to_path_buf(), state);
+        }
+    }
+
+    fn simulated(&self, path: &Path) -> Option<Simulated> {
+        if !self.dry_run {
+            return None;
+        }
+        let simulated = self.simulated.borrow();
+        if let Some(state) = simulated.get(path) {
+            return Some(*state);
+        }
+        for ancestor in path.ancestors().skip(1) {
+            match simulated.get(ancestor) {
+                Some(Simulated::Dir) | None => continue,
+                Some(_) => return Some(Simulated::Missing),
+            }
+        }
+        None
+    }
+
+    pub(crate) fn try_exists(&self, path: &Path) -> io::Result<bool> {
+        match self.simulated(path) {
+            Some(state) => Ok(state != Simulated::Missing),
+            None => path.try_exists(),
+        }
+    }
+
+    pub(crate) fn exists(&self, path: &Path) -> bool {
+        self.try_exists(path).unwrap_or(false)
+    }
+
+    pub(crate) fn is_file(&self, path: &Path) -> bool {
+        match self.simulated(path) {
+            Some(state) => state == Simulated::File,
+            None => path.is_file(),
+        }
+    }
+
+    pub(crate) fn is_dir(&self, path: &Path) -> bool {
+        match self.simulated(path) {
+            Some(state) => state == Simulated::Dir || state == Simulated::EmptyDir,
+            None => path.is_dir(),
+        }
+    }
+
+    /// Whether `link` is a symlink (or junction) pointing to `target`.
+    pub(crate) fn points_to(&self, link: &Path, target: &Path) -> bool {
+        if self.simulated(link).is_some() {
+            return false;
+        }
+        read_link_absolute(link).is_ok_and(|t| t == normalize(target))
+    }
+
+    pub(crate) fn is_dir_empty(&self, dir: &Path) -> Result<bool> {
+        match self.simulated(dir) {
+            Some(state) => Ok(state != Simulated::Dir),
+            None => {
+                let entries = dir.read_dir().map_err(|e| ImplinkError::Io {
+                    path: dir.to_path_buf(),
+                    source: e,
+                })?;
+                // In a dry run, entries which would have been removed don't count
+                for entry in entries {
+                    let entry = entry.map_err(|e| ImplinkError::Io {
+                        path: dir.to_path_buf(),
+                        source: e,
+                    })?;
+                    if self.simulated(&entry.path()) != Some(Simulated::Missing) {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    pub(crate) fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        let missing: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|path| !self.exists(path))
+            .map(Path::to_path_buf)
+            .collect();
+        if self.perform(Action::CreateDir {
+            path: dir.to_path_buf(),
+        }) {
+            create_dir_all(dir).map_err(|e| ImplinkError::Io {
+                path: dir.to_path_buf(),
+                source: e,
+            })?;
+        }
+        // Innermost last, so it is removed first
+        for created in missing.into_iter().rev() {
+            self.record(Undo::RemoveDir(created));
+        }
+        self.simulate(dir, Simulated::EmptyDir);
+        Ok(())
+    }
+
+    /// Removes `dir` if it is an empty directory.
+    pub(crate) fn remove_empty_dir(&self, dir: &Path) -> bool {
+        if self.dry_run {
+            if !self.is_dir(dir) || !self.is_dir_empty(dir).unwrap_or(false) {
+                return false;
+            }
+        } else if remove_dir(dir).is_err() {
+            return false;
+        }
+        self.record(Undo::CreateDir(dir.to_path_buf()));
+        self.perform(Action::RemoveDir {
+            path: dir.to_path_buf(),
+        });
+        self.simulate(dir, Simulated::Missing);
+        true
+    }
+
+    /// Removes a symlink or junction without touching what it points to.
+    pub(crate) fn remove_link(&self, link: &Path) -> Result<()> {
+        if self.perform(Action::RemoveLink {
+            path: link.to_path_buf(),
+        }) {
+            // Directory symlinks and junctions on Windows have to be removed as directories
+            remove_file(link)
+                .or_else(|_| remove_dir(link))
+                .map_err(|e| ImplinkError::RemoveFailed {
+                    path: link.to_path_buf(),
+                    source: e,
+                })?;
+            self.report(Event::Unlinked {
+                dst: link.to_path_buf(),
+            });
+        }
+        self.simulate(link, Simulated::Missing);
+        Ok(())
+    }
+
+    /// Removes the parent directories of `path` as long as they are empty.
+    ///
+    /// Never goes up to the home directory or the filesystem root.
+    pub(crate) fn prune_empty_parents(&self, path: &Path) {
+        let home = home_dir();
+        for parent in path.ancestors().skip(1) {
+            if parent.parent().is_none() || Some(parent) == home.as_deref() {
+                break;
+            }
+            if !self.remove_empty_dir(parent) {
+                break;
+            }
+        }
+    }
+
+    /// Reads where the link at `link` points to, failing if it isn't a link.
+    pub(crate) fn link_target(&self, link: &Path) -> Result<PathBuf> {
+        let not_a_link = || ImplinkError::NotALink {
+            path: link.to_path_buf(),
+        };
+        if self.simulated(link).is_some() {
+            return Err(not_a_link());
+        }
+        match symlink_metadata(link) {
+            Ok(metadata) if metadata.file_type().is_symlink() => (),
+            Ok(_) => return Err(not_a_link()),
+            Err(e) => {
+                return Err(ImplinkError::Io {
+                    path: link.to_path_buf(),
+                    source: e,
+                })
+            }
+        }
+        read_link_absolute(link).map_err(|e| ImplinkError::Io {
+            path: link.to_path_buf(),
+            source: e,
+        })
+    }
+
+    pub(crate) fn move_file_or_directory(&self, src: &Path, dst: &Path, force: bool) -> Result<()> {
+        self.move_kept(src, dst, force, &Ignore::default())
+    }
+
+    /// Rules for the tree at `root`, with the extra ignore patterns.
+    pub(crate) fn ignore(&self, root: &Path) -> Result<Ignore> {
+        Ignore::new(root, &self.ignore)
+    }
+
+    /// Moves everything in `src` which `ignore` doesn't ignore to `dst`.
+    ///
+    /// Ignored entries stay where they are, together with the directories they are in.
+    pub(crate) fn move_kept(
+        &self,
+        src: &Path,
+        dst: &Path,
+        force: bool,
+        ignore: &Ignore,
+    ) -> Result<()> {
+        let move_failed = |e: io::Error| match mover::interruption(&e) {
+            Some(interrupted) => ImplinkError::Interrupted {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+                files: interrupted.files,
+                bytes: interrupted.bytes,
+            },
+            None => ImplinkError::MoveFailed {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+                source: e,
+            },
+        };
+        if !self.exists(src) {
+            return Err(ImplinkError::SourceMissing {
+                path: src.to_path_buf(),
+            });
+        }
+        // Sources only created earlier in a dry run can't be checked
+        if self.preflight && self.simulated(src).is_none() {
+            preflight::check(src, dst, ignore)?;
+        }
+        if self.is_file(src) {
+            if self.exists(dst) {
+                if !force {
+                    return Err(ImplinkError::DestinationExists {
+                        path: dst.to_path_buf(),
+                    });
+                }
+                self.replace_existing(dst)?;
+            }
+            if self.perform(Action::MoveFile {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+            }) {
+                let hash = mover::move_file(src, dst, &self.copy, |copied, total| {
+                    self.report(Event::MoveProgress {
+                        file: src.to_path_buf(),
+                        dst: dst.to_path_buf(),
+                        percent: copied * 100 / total.max(1),
+                    })
+                })
+                .map_err(move_failed)?;
+                if let Some(hash) = hash {
+                    self.checksums.borrow_mut().push((dst.to_path_buf(), hash));
+                }
+            }
+            self.simulate(src, Simulated::Missing);
+            self.simulate(dst, Simulated::File);
+        } else {
+            // Part of the data is already at `dst` if a previous move has been interrupted
+            let resuming = self.simulated(dst).is_none()
+                && symlink_metadata(src).is_ok_and(|m| m.is_dir())
+                && MoveJournal::exists(src, dst);
+            // Sources only created earlier in a dry run have nothing ignored in them
+            let partial = self.simulated(src).is_none() && ignore.ignores_any(src)?;
+            if resuming {
+                if !self.dry_run {
+                    self.report(Event::Resuming {
+                        src: src.to_path_buf(),
+                        dst: dst.to_path_buf(),
+                    });
+                }
+            } else {
+                if self.exists(dst) && !self.is_dir(dst) {
+                    if !force {
+                        return Err(ImplinkError::DestinationExists {
+                            path: dst.to_path_buf(),
+                        });
+                    }
+                    self.replace_existing(dst)?;
+                } else if self.exists(dst) && !self.is_dir_empty(dst)? {
+                    if !force {
+                        return Err(ImplinkError::DestinationNotEmpty {
+                            path: dst.to_path_buf(),
+                        });
+                    }
+                    self.replace_existing(dst)?;
+                }
+                if !partial && self.rename_dir(src, dst)? {
+                    self.report_moved(src, dst);
+                    return Ok(());
+                }
+            }
+            if !self.exists(dst) {
+                self.create_dir_all(dst)?;
+            }
+            if self.dry_run && partial {
+                for part in ignore.kept_parts(src)?.unwrap_or_default() {
+                    let (from, to) = (src.join(&part), dst.join(&part));
+                    let is_dir = from.is_dir();
+                    self.perform(if is_dir {
+                        Action::MoveDir {
+                            src: from.clone(),
+                            dst: to.clone(),
+                        }
+                    } else {
+                        Action::MoveFile {
+                            src: from.clone(),
+                            dst: to.clone(),
+                        }
+                    });
+                    self.simulate(&from, Simulated::Missing);
+                    self.simulate(
+                        &to,
+                        if is_dir {
+                            Simulated::Dir
+                        } else {
+                            Simulated::File
+                        },
+                    );
+                }
+                self.simulate(dst, Simulated::Dir);
+            } else if self.dry_run {
+                let entries = src.read_dir().map_err(|e| ImplinkError::Io {
+                    path: src.to_path_buf(),
+                    source: e,
+                })?;
+                for entry in entries {
+                    let path = entry.map_err(move_failed)?.path();
+                    let target = dst.join(path.file_name().unwrap_or_default());
+                    let is_dir = path.is_dir();
+                    self.perform(if is_dir {
+                        Action::MoveDir {
+                            src: path,
+                            dst: target.clone(),
+                        }
+                    } else {
+                        Action::MoveFile {
+                            src: path,
+                            dst: target.clone(),
+                        }
+                    });
+                    self.simulate(
+                        &target,
+                        if is_dir {
+                            Simulated::Dir
+                        } else {
+                            Simulated::File
+                        },
+                    );
+                    self.simulate(dst, Simulated::Dir);
+                }
+            } else {
+                let mut journal = MoveJournal::open(src, dst).map_err(|e| ImplinkError::Io {
+                    path: MoveJournal::path(dst),
+                    source: e,
+                })?;
+                let progress = |file: &Path, copied: u64, total: u64| {
+                    self.report(Event::MoveProgress {
+                        file: file.to_path_buf(),
+                        dst: dst.to_path_buf(),
+                        percent: copied * 100 / total.max(1),
+                    })
+                };
+                let hashes = mover::move_tree(src, dst, &self.copy, &mut journal, ignore, progress)
+                    .map_err(move_failed)?;
+                self.checksums.borrow_mut().extend(hashes);
+            }
+            if !partial {
+                self.simulate(src, Simulated::EmptyDir);
+            }
+        }
+        self.report_moved(src, dst);
+        Ok(())
+    }
+
+    /// Forgets about a move to `dst` once it is complete, so it isn't resumed again.
+    ///
+    /// Kept apart from [`Ops::move_file_or_directory`] so a move-and-link interrupted
+    /// right before the link is created still resumes.
+    pub(crate) fn finish_move(&self, dst: &Path) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        MoveJournal::remove(dst).map_err(|e| ImplinkError::Io {
+            path: MoveJournal::path(dst),
+            source: e,
+        })
+    }
+
+    fn report_moved(&self, src: &Path, dst: &Path) {
+        if !self.dry_run {
+            self.report(Event::Moved {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+            });
+        }
+    }
+
+    /// Moves a whole directory in one step if `dst` is on the same filesystem.
+    ///
+    /// `dst` must not exist or be an empty directory. Returns `false` if the
+    /// contents have to be copied instead.
+    fn rename_dir(&self, src: &Path, dst: &Path) -> Result<bool> {
+        let same_device = mover::same_device(src, dst);
+        if same_device == Some(false) {
+            return Ok(false);
+        }
+        // Copying creates the whole path to `dst`, renaming only the last component
+        if let Some(parent) = dst.parent() {
+            if !self.exists(parent) {
+                self.create_dir_all(parent)?;
+            }
+        }
+        if self.dry_run {
+            self.perform(Action::MoveDir {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+            });
+            self.simulate(src, Simulated::Missing);
+            self.simulate(dst, Simulated::Dir);
+            return Ok(true);
+        }
+        if self.exists(dst) {
+            remove_dir(dst).map_err(|e| ImplinkError::RemoveFailed {
+                path: dst.to_path_buf(),
+                source: e,
+            })?;
+        }
+        match rename(src, dst) {
+            Ok(_) => Ok(true),
+            Err(e) if mover::is_cross_device(&e) => Ok(false),
+            Err(e) => Err(ImplinkError::MoveFailed {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+                source: e,
+            }),
+        }
+    }
+
+    /// Gets an existing destination out of the way, backing it up if enabled.
+    pub(crate) fn replace_existing(&self, dst: &Path) -> Result<()> {
+        let Some(mode) = &self.backup else {
+            return self.rm_rf(dst);
+        };
+        let backup = mode.backup_path(dst);
+        if self.perform(Action::Backup {
+            src: dst.to_path_buf(),
+            dst: backup.clone(),
+        }) {
+            let backup_failed = |e| ImplinkError::MoveFailed {
+                src: dst.to_path_buf(),
+                dst: backup.clone(),
+                source: e,
+            };
+            if let Some(parent) = backup.parent() {
+                create_dir_all(parent).map_err(backup_failed)?;
+            }
+            if let Err(e) = rename(dst, &backup) {
+                // Backup directories may be on another filesystem, links can't be copied there
+                let is_link = symlink_metadata(dst).is_ok_and(|m| m.file_type().is_symlink());
+                if is_link || !self.exists(dst) {
+                    return Err(backup_failed(e));
+                }
+                self.move_file_or_directory(dst, &backup, false)?;
+                self.finish_move(&backup)?;
+                if self.is_dir(dst) {
+                    remove_dir(dst).map_err(backup_failed)?;
+                }
+            }
+            self.record(Undo::Unbackup {
+                backup: backup.clone(),
+                original: dst.to_path_buf(),
+            });
+            self.backups.borrow_mut().push(BackupEntry {
+                original: dst.to_path_buf(),
+                backup: backup.clone(),
+                created: timestamp(),
+            });
+            self.report(Event::BackedUp {
+                src: dst.to_path_buf(),
+                backup,
+            });
+        }
+        self.simulate(dst, Simulated::Missing);
+        Ok(())
+    }
+
+    pub(crate) fn rm_rf(&self, dst: &Path) -> Result<()> {
+        let is_file = self.is_file(dst);
+        let action = if is_file {
+            Action::RemoveFile {
+                path: dst.to_path_buf(),
+            }
+        } else {
+            Action::RemoveDir {
+                path: dst.to_path_buf(),
+            }
+        };
+        if self.perform(action) {
+            let result = if self.journal.is_some() {
+                self.stash(dst)
+            } else if is_file {
+                remove_file(dst)
+            } else {
+                remove_dir_all(dst)
+            };
+            // I do love Windows bro ("truly" :D)
+            // Fallback to "del" command which works on Windows :D
+            // del documentation: https://learn.microsoft.com/en-us/windows-server/administration/windows-commands/del
+            #[cfg(target_os = "windows")]
+            let result = result.or_else(|e| {
+                if self.journal.is_some() {
+                    return Err(e);
+                }
+                // This should be equivalent to "rm -rf" on Unix-like systems
+                let mut command = Command::new("cmd");
+                command
+                    .arg("/C")
+                    .arg("del")
+                    .args(["/f", "/q", "/s"])
+                    .arg(dst);
+                command.output().map(|_| ())
+            });
+            result.map_err(|e| ImplinkError::RemoveFailed {
+                path: dst.to_path_buf(),
+                source: e,
+            })?;
+        }
+        self.simulate(dst, Simulated::Missing);
+        Ok(())
+    }
+
+    /// Gets `dst` out of the way before a link is created there: empty directories are
+    /// removed, anything else only with `force`.
+    fn clear_destination(&self, dst: &Path, force: bool) -> Result<()> {
+        let dst_exists = match self.try_exists(dst) {
+            Ok(result) => result,
+            Err(e) => {
+                if !force {
+                    return Err(ImplinkError::Io {
+                        path: dst.to_path_buf(),
+                        source: e,
+                    });
+                }
+                true
+            }
+        };
+        if dst_exists {
+            if !force {
+                if !self.remove_empty_dir(dst) {
+                    return Err(ImplinkError::DestinationExists {
+                        path: dst.to_path_buf(),
+                    });
+                }
+            } else {
+                self.replace_existing(dst)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn make_symlink(
+        &self,
+        src: &Path,
+        dst: &Path,
+        force: bool,
+        use_junction: bool,
+        relative: bool,
+    ) -> Result<()> {
+        let link_failed = |e: io::Error| ImplinkError::LinkFailed {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+            source: e,
+        };
+        if !self.exists(src) {
+            return Err(ImplinkError::SourceMissing {
+                path: src.to_path_buf(),
+            });
+        }
+        self.clear_destination(dst, force)?;
+        let src_is_dir = self.is_dir(src);
+        let junction = cfg!(target_os = "windows") && use_junction && src_is_dir;
+        let target = match dst.parent() {
+            Some(parent) if relative && !junction => relative_to(src, parent),
+            _ => src.to_path_buf(),
+        };
+        if self.perform(Action::Symlink {
+            src: target.clone(),
+            dst: dst.to_path_buf(),
+            junction,
+        }) {
+            if let Err(e) = _make_symlink(src, &target, dst, use_junction) {
+                if !force {
+                    return Err(link_failed(e));
+                }
+                // Workaround :)
+                self.report(Event::Retrying {
+                    dst: dst.to_path_buf(),
+                    reason: e.to_string(),
+                });
+                // Return if we can't even remove the destination
+                self.rm_rf(dst)?;
+                _make_symlink(src, &target, dst, use_junction).map_err(link_failed)?;
+            }
+            self.record(Undo::RemoveLink(dst.to_path_buf()));
+            self.report(Event::Symlinked {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+            });
+        }
+        self.simulate(
+            dst,
+            if src_is_dir {
+                Simulated::Dir
+            } else {
+                Simulated::File
+            },
+        );
+        Ok(())
+    }
+
+    /// Fails if `src` and `dst` are on different filesystems, which hard links can't span.
+    pub(crate) fn check_same_device(&self, src: &Path, dst: &Path) -> Result<()> {
+        if mover::same_device(src, dst) == Some(false) {
+            return Err(ImplinkError::CrossDevice {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Hard links `dst` to the file `src`, or mirrors the directory `src` at `dst` with
+    /// every file hard linked.
+    pub(crate) fn make_hard_link(&self, src: &Path, dst: &Path, force: bool) -> Result<()> {
+        if !self.exists(src) {
+            return Err(ImplinkError::SourceMissing {
+                path: src.to_path_buf(),
+            });
+        }
+        self.check_same_device(src, dst)?;
+        let ignore = self.ignore(src)?;
+        self.clear_destination(dst, force)?;
+        if self.perform(Action::HardLink {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+        }) {
+            mover::hard_link_tree(src, dst, &ignore).map_err(|e| {
+                // Don't leave half a mirror behind
+                if self.is_dir(src) {
+                    let _ = remove_any(dst);
+                }
+                ImplinkError::LinkFailed {
+                    src: src.to_path_buf(),
+                    dst: dst.to_path_buf(),
+                    source: e,
+                }
+            })?;
+            self.record(Undo::RemoveHardLink(dst.to_path_buf()));
+            self.report(Event::HardLinked {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+            });
+        }
+        self.simulate(
+            dst,
+            if self.is_dir(src) {
+                Simulated::Dir
+            } else {
+                Simulated::File
+            },
+        );
+        Ok(())
+    }
+
+    /// Creates a link of the given kind at `dst` pointing to `src`.
+    ///
+    /// With `relative`, symlinks store the path to `src` relative to the directory of `dst`.
+    pub(crate) fn make_link(
+        &self,
+        src: &Path,
+        dst: &Path,
+        kind: LinkKind,
+        force: bool,
+        use_junction: bool,
+        relative: bool,
+    ) -> Result<()> {
+        match kind {
+            LinkKind::Symlink => self.make_symlink(src, dst, force, use_junction, relative),
+            LinkKind::Hard => self.make_hard_link(src, dst, force),
+        }
+    }
+
+    /// Whether `link` is a link of the given kind to `target`.
+    pub(crate) fn is_linked(&self, link: &Path, target: &Path, kind: LinkKind) -> bool {
+        match kind {
+            LinkKind::Symlink => self.points_to(link, target),
+            LinkKind::Hard => {
+                self.simulated(link).is_none()
+                    && self
+                        .ignore(target)
+                        .is_ok_and(|ignore| mover::is_hard_linked(target, link, &ignore))
+            }
+        }
+    }
+
+    /// Removes a hard link or a mirrored tree of them, leaving the source alone.
+    pub(crate) fn remove_hard_link(&self, link: &Path) -> Result<()> {
+        if self.perform(Action::RemoveLink {
+            path: link.to_path_buf(),
+        }) {
+            remove_any(link).map_err(|e| ImplinkError::RemoveFailed {
+                path: link.to_path_buf(),
+                source: e,
+            })?;
+            self.report(Event::Unlinked {
+                dst: link.to_path_buf(),
+            });
+        }
+        self.simulate(link, Simulated::Missing);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read_to_string, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn dry_run_plans_on_the_simulated_state() {
+        let tmp = tempdir().unwrap();
+        let (src, dst, moved) = (
+            tmp.path().join("src"),
+            tmp.path().join("dst"),
+            tmp.path().join("moved"),
+        );
+        create_dir_all(&src).unwrap();
+        write(src.join("a"), "a").unwrap();
+        write(&dst, "old").unwrap();
+        let events = RefCell::new(Vec::new());
+        let report = |event| events.borrow_mut().push(event);
+        let ops = Ops::new(&report, true);
+
+        ops.make_symlink(&src, &dst, true, false, false).unwrap();
+        // The destination is a link now, a second one would have to replace it too
+        assert!(matches!(
+            ops.make_symlink(&src, &dst, false, false, false),
+            Err(ImplinkError::DestinationExists { .. })
+        ));
+        ops.move_file_or_directory(&src, &moved, false).unwrap();
+        assert!(!ops.exists(&src));
+        assert!(!ops.exists(&src.join("a")));
+        assert!(ops.is_dir(&moved));
+
+        assert_eq!(read_to_string(&dst).unwrap(), "old");
+        assert!(src.join("a").exists());
+        assert!(!moved.exists());
+        let events = events.into_inner();
+        assert!(matches!(
+            events[..2],
+            [
+                Event::Planned(Action::RemoveFile { .. }),
+                Event::Planned(Action::Symlink { .. })
+            ]
+        ));
+        assert!(events.iter().all(|e| matches!(e, Event::Planned(_))));
+    }
+}
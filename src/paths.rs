@@ -0,0 +1,301 @@
+use crate::error::{ImplinkError, Result};
+use std::env::{home_dir, var};
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
+
+/// Default values of the XDG base directories, used when the variable is not set.
+const XDG_DEFAULTS: [(&str, &str); 4] = [
+    ("XDG_CONFIG_HOME", "~/.config"),
+    ("XDG_DATA_HOME", "~/.local/share"),
+    ("XDG_STATE_HOME", "~/.local/state"),
+    ("XDG_CACHE_HOME", "~/.cache"),
+];
+
+fn home(path: &str) -> Result<PathBuf> {
+    home_dir().ok_or_else(|| ImplinkError::UndefinedVariable {
+        name: "HOME".to_string(),
+        path: path.to_string(),
+    })
+}
+
+/// Looks up a variable, falling back to `default` and then to the XDG defaults.
+fn lookup(name: &str, default: Option<&str>, path: &str) -> Result<String> {
+    if let Ok(value) = var(name) {
+        if !value.is_empty() {
+            return Ok(value);
+        }
+    }
+    if name == "HOME" {
+        return Ok(home(path)?.to_string_lossy().into_owned());
+    }
+    let fallback = default.or_else(|| {
+        XDG_DEFAULTS
+            .iter()
+            .find(|(xdg, _)| *xdg == name)
+            .map(|(_, value)| *value)
+    });
+    match fallback {
+        Some(fallback) => expand(fallback),
+        None => Err(ImplinkError::UndefinedVariable {
+            name: name.to_string(),
+            path: path.to_string(),
+        }),
+    }
+}
+
+/// Whether `path` starts with a `~` standing for the home directory.
+fn starts_at_home(path: &str) -> bool {
+    path == "~" || path.starts_with("~/") || path.starts_with("~\\")
+}
+
+/// Expands a leading `~` and `$VAR`, `${VAR}` or `${VAR:-default}` variables, `$$` is a
+/// literal `$`.
+///
+/// Unset XDG base directory variables expand to their default locations.
+pub(crate) fn expand(path: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = path;
+    if starts_at_home(rest) {
+        expanded.push_str(&home(path)?.to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(index) = rest.find('$') {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        if let Some(escaped) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = escaped;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                return Err(ImplinkError::UndefinedVariable {
+                    name: braced.to_string(),
+                    path: path.to_string(),
+                });
+            };
+            let (name, default) = match braced[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&braced[..end], None),
+            };
+            expanded.push_str(&lookup(name, default, path)?);
+            rest = &braced[end + 1..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                // A lone '$' is kept as it is
+                expanded.push('$');
+                continue;
+            }
+            expanded.push_str(&lookup(&rest[..end], None, path)?);
+            rest = &rest[end..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Escapes `path` so [`expand`] gives it back as it is: `$` is doubled and a leading `~`
+/// becomes `./~`.
+pub(crate) fn escape(path: &str) -> String {
+    let escaped = path.replace('$', "$$");
+    if starts_at_home(&escaped) {
+        return format!(".{}{}", MAIN_SEPARATOR, escaped);
+    }
+    escaped
+}
+
+/// Expands `path` and resolves it against `base` if it is relative.
+pub(crate) fn resolve_in(path: &str, base: &Path) -> Result<PathBuf> {
+    let expanded = PathBuf::from(expand(path)?);
+    if expanded.is_absolute() {
+        return Ok(expanded);
+    }
+    Ok(base.join(expanded))
+}
+
+/// Turns an absolute path into a form which works on other machines, escaped for
+/// [`expand`].
+///
+/// Paths inside `base` become relative to it, paths inside the home directory
+/// start with `~`, anything else is kept as it is.
+pub(crate) fn portable(path: &Path, base: Option<&Path>) -> String {
+    if let Some(relative) = base.and_then(|base| path.strip_prefix(base).ok()) {
+        let inside = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+        if inside && !relative.as_os_str().is_empty() {
+            return escape(&relative.to_string_lossy());
+        }
+    }
+    if let Some(relative) = home_dir()
+        .as_deref()
+        .and_then(|home| path.strip_prefix(home).ok())
+    {
+        if relative.as_os_str().is_empty() {
+            return "~".to_string();
+        }
+        let relative = relative.to_string_lossy().replace('$', "$$");
+        return format!("~{}{}", MAIN_SEPARATOR, relative);
+    }
+    escape(&path.to_string_lossy())
+}
+
+/// Removes `.` components and resolves `..` against the component before it, without
+/// looking at the filesystem.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            // `..` of the root is the root itself
+            Component::ParentDir if normalized.has_root() => (),
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Shortest relative path leading from the directory `base` to `path`, both absolute.
+///
+/// Only the paths themselves are compared, symlinks in `base` aren't resolved. `path` is
+/// returned as it is if the two don't share a root, like on different Windows drives.
+pub(crate) fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path = normalize(path);
+    let base = normalize(base);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let roots = path
+        .components()
+        .take_while(|c| matches!(c, Component::Prefix(_) | Component::RootDir))
+        .count();
+    if common < roots {
+        return path;
+    }
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(path.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_home_and_variables() {
+        let home = home_dir().unwrap();
+        assert_eq!(expand("~").unwrap(), home.to_string_lossy());
+        assert_eq!(
+            expand("~/.vimrc").unwrap(),
+            format!("{}/.vimrc", home.to_string_lossy())
+        );
+        assert_eq!(
+            expand("$HOME/a").unwrap(),
+            format!("{}/a", home.to_string_lossy())
+        );
+        assert_eq!(
+            expand("${IMPLINK_TEST_UNSET:-/opt/x}/a").unwrap(),
+            "/opt/x/a"
+        );
+        assert!(matches!(
+            expand("$IMPLINK_TEST_UNSET/a"),
+            Err(ImplinkError::UndefinedVariable { name, .. }) if name == "IMPLINK_TEST_UNSET"
+        ));
+        assert!(expand("${IMPLINK_TEST_UNSET").is_err());
+    }
+
+    #[test]
+    fn keeps_lone_dollars_and_tildes() {
+        assert_eq!(expand("a$").unwrap(), "a$");
+        assert_eq!(expand("a$/b").unwrap(), "a$/b");
+        assert_eq!(expand("~user/a").unwrap(), "~user/a");
+        assert_eq!(expand("a/~").unwrap(), "a/~");
+    }
+
+    #[test]
+    fn escaped_paths_expand_to_themselves() {
+        assert_eq!(expand("cost$$USD").unwrap(), "cost$USD");
+        for path in ["/tmp/cost$USD", "/a/${B}/$$", "plain/path", "a$"] {
+            assert_eq!(expand(&escape(path)).unwrap(), path);
+        }
+        // A directory named `~` stays one
+        let base = Path::new("/srv/dots");
+        for path in ["~", "~/a"] {
+            assert_eq!(resolve_in(&escape(path), base).unwrap(), base.join(path));
+        }
+    }
+
+    #[test]
+    fn portable_paths_are_escaped() {
+        let base = Path::new("/srv/dots");
+        assert_eq!(
+            portable(Path::new("/srv/dots/vim/rc"), Some(base)),
+            "vim/rc"
+        );
+        assert_eq!(portable(Path::new("/srv/dots/$x"), Some(base)), "$$x");
+        assert_eq!(portable(Path::new("/srv/dots"), Some(base)), "/srv/dots");
+        assert_eq!(portable(Path::new("/srv/other"), Some(base)), "/srv/other");
+        let home = home_dir().unwrap();
+        assert_eq!(portable(&home, None), "~");
+        assert_eq!(
+            expand(&portable(&home.join("a$b"), None)).unwrap(),
+            home.join("a$b").to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn normalizes_lexically() {
+        let normalized = |path: &str| normalize(Path::new(path));
+        assert_eq!(normalized("/a/./b/../c"), PathBuf::from("/a/c"));
+        assert_eq!(normalized("/a/b/../../.."), PathBuf::from("/"));
+        assert_eq!(normalized("/../a"), PathBuf::from("/a"));
+        assert_eq!(normalized("a/../../b"), PathBuf::from("../b"));
+        assert_eq!(normalized("./a/"), PathBuf::from("a"));
+        assert_eq!(normalized("a/.."), PathBuf::new());
+    }
+
+    #[test]
+    fn relative_paths_between_directories() {
+        let relative = |path: &str, base: &str| relative_to(Path::new(path), Path::new(base));
+        assert_eq!(
+            relative("/dots/vim/rc", "/home/u"),
+            PathBuf::from("../../dots/vim/rc")
+        );
+        assert_eq!(
+            relative("/home/u/dots/rc", "/home/u"),
+            PathBuf::from("dots/rc")
+        );
+        assert_eq!(
+            relative("/home/u", "/home/u/.config/app"),
+            PathBuf::from("../..")
+        );
+        assert_eq!(relative("/home/u", "/home/u"), PathBuf::from("."));
+        assert_eq!(relative("/a/b", "/a/x/../y/."), PathBuf::from("../b"));
+        assert_eq!(relative("/a", "/"), PathBuf::from("a"));
+        // Nothing in common, not even the root
+        assert_eq!(relative("/a", "b"), PathBuf::from("/a"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn paths_on_other_drives_stay_absolute() {
+        let path = Path::new(r"D:\dots\rc");
+        assert_eq!(relative_to(path, Path::new(r"C:\Users\u")), path);
+    }
+}
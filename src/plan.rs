@@ -0,0 +1,90 @@
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+
+/// A single filesystem change, as listed by a dry run.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    RemoveFile {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    MoveFile {
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    MoveDir {
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    /// Move an existing destination at `src` aside to `dst`
+    Backup {
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    /// Remove a symlink or junction, leaving its target alone
+    RemoveLink {
+        path: PathBuf,
+    },
+    /// Create a link at `dst` pointing to `src`, which is relative to the directory of
+    /// `dst` for relative symlinks
+    Symlink {
+        src: PathBuf,
+        dst: PathBuf,
+        junction: bool,
+    },
+    /// Hard link `dst` to `src`, mirroring directories
+    HardLink {
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    WriteMapping {
+        file: PathBuf,
+    },
+    WriteManifest {
+        file: PathBuf,
+    },
+    /// Write the hashes of moved files
+    WriteChecksums {
+        file: PathBuf,
+    },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::RemoveFile { path } => write!(f, "remove file {}", path.display()),
+            Action::RemoveDir { path } => write!(f, "remove dir {}", path.display()),
+            Action::Backup { src, dst } => {
+                write!(f, "backup {} -> {}", src.display(), dst.display())
+            }
+            Action::RemoveLink { path } => write!(f, "remove link {}", path.display()),
+            Action::CreateDir { path } => write!(f, "create dir {}", path.display()),
+            Action::MoveFile { src, dst } => {
+                write!(f, "move file {} -> {}", src.display(), dst.display())
+            }
+            Action::MoveDir { src, dst } => {
+                write!(f, "move dir {} -> {}", src.display(), dst.display())
+            }
+            Action::Symlink { src, dst, junction } => write!(
+                f,
+                "{} {} -> {}",
+                if *junction { "junction" } else { "symlink" },
+                dst.display(),
+                src.display()
+            ),
+            Action::HardLink { src, dst } => {
+                write!(f, "hard link {} -> {}", dst.display(), src.display())
+            }
+            Action::WriteMapping { file } => write!(f, "write mapping {}", file.display()),
+            Action::WriteManifest { file } => write!(f, "write manifest {}", file.display()),
+            Action::WriteChecksums { file } => write!(f, "write checksums {}", file.display()),
+        }
+    }
+}
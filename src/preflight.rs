@@ -0,0 +1,260 @@
+use crate::error::{ImplinkError, Result};
+use crate::ignores::Ignore;
+use crate::mover;
+use std::path::Path;
+
+/// Number of problems listed before the rest are only counted.
+const MAX_PROBLEMS: usize = 10;
+
+/// Free space and inodes on a filesystem, `None` where it isn't reported.
+struct Available {
+    bytes: u64,
+    inodes: Option<u64>,
+}
+
+#[cfg(not(target_os = "windows"))]
+fn c_path(path: &Path) -> Option<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes()).ok()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn available(path: &Path) -> Option<Available> {
+    let path = c_path(path)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(Available {
+        bytes: stat.f_bavail as u64 * stat.f_frsize as u64,
+        // Filesystems without a fixed number of inodes report none at all
+        inodes: (stat.f_files != 0).then_some(stat.f_favail as u64),
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn available(_: &Path) -> Option<Available> {
+    None
+}
+
+/// Whether the process may read (and list, for directories) `path`.
+#[cfg(not(target_os = "windows"))]
+fn can_read(path: &Path, is_dir: bool) -> bool {
+    let mode = if is_dir {
+        libc::R_OK | libc::X_OK
+    } else {
+        libc::R_OK
+    };
+    c_path(path).is_some_and(|path| unsafe { libc::access(path.as_ptr(), mode) } == 0)
+}
+
+/// Whether the process may create and remove entries in the directory `dir`.
+#[cfg(not(target_os = "windows"))]
+fn can_write(dir: &Path) -> bool {
+    c_path(dir)
+        .is_some_and(|dir| unsafe { libc::access(dir.as_ptr(), libc::W_OK | libc::X_OK) } == 0)
+}
+
+#[cfg(target_os = "windows")]
+fn can_read(_: &Path, _: bool) -> bool {
+    true
+}
+
+#[cfg(target_os = "windows")]
+fn can_write(_: &Path) -> bool {
+    true
+}
+
+/// Size and number of entries of a tree, and everything in it which can't be moved.
+#[derive(Default)]
+struct Tree {
+    bytes: u64,
+    inodes: u64,
+    problems: Vec<String>,
+}
+
+impl Tree {
+    fn walk(&mut self, path: &Path, ignore: &Ignore) -> Result<()> {
+        let metadata = path.symlink_metadata().map_err(|e| ImplinkError::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        self.inodes += 1;
+        if metadata.file_type().is_symlink() {
+            return Ok(());
+        }
+        if !metadata.is_dir() {
+            self.bytes += metadata.len();
+            if !can_read(path, false) {
+                self.problems
+                    .push(format!("'{}' can't be read", path.display()));
+            }
+            return Ok(());
+        }
+        if !can_read(path, true) {
+            self.problems
+                .push(format!("'{}' can't be read", path.display()));
+            return Ok(());
+        }
+        if !can_write(path) {
+            self.problems
+                .push(format!("nothing can be removed from '{}'", path.display()));
+        }
+        let entries = path.read_dir().map_err(|e| ImplinkError::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        let ignore = ignore.child(path)?;
+        for entry in entries {
+            let entry = entry.map_err(|e| ImplinkError::Io {
+                path: path.to_path_buf(),
+                source: e,
+            })?;
+            let path = entry.path();
+            // Ignored entries stay where they are
+            if !ignore.is_ignored(&path, path.symlink_metadata().is_ok_and(|m| m.is_dir())) {
+                self.walk(&path, &ignore)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `src` can be moved to `dst` before anything is touched: every file can
+/// be read and removed, `dst` can be created, and its filesystem has enough free space
+/// and inodes if the data has to be copied there. Ignored entries aren't moved.
+///
+/// A move within the same filesystem is a single rename which doesn't touch the files,
+/// so only the directories `src` leaves and `dst` is created in are checked then.
+pub(crate) fn check(src: &Path, dst: &Path, ignore: &Ignore) -> Result<()> {
+    let same_device = mover::same_device(src, dst);
+    // Directories with ignored entries are moved entry by entry instead
+    let renamed = same_device == Some(true) && !ignore.ignores_any(src)?;
+    let mut tree = Tree::default();
+    if !renamed {
+        tree.walk(src, ignore)?;
+    } else if src.is_dir() && src.parent() != dst.parent() && !can_write(src) {
+        // Moving a directory elsewhere updates its `..` entry
+        tree.problems.push(format!(
+            "'{}' can't be moved to another directory",
+            src.display()
+        ));
+    }
+    if let Some(parent) = src.parent() {
+        if !can_write(parent) {
+            tree.problems.push(format!(
+                "'{}' can't be removed from '{}'",
+                src.display(),
+                parent.display()
+            ));
+        }
+    }
+    // `dst` itself if it is an empty directory to move into, otherwise the parent it's created in
+    let existing = dst.ancestors().find(|p| p.is_dir());
+    if let Some(existing) = existing {
+        if !can_write(existing) {
+            tree.problems
+                .push(format!("'{}' can't be written to", existing.display()));
+        }
+        // Directories with ignored entries are renamed entry by entry on the same filesystem
+        let copied = same_device == Some(false);
+        if let Some(available) = copied.then(|| available(existing)).flatten() {
+            if available.bytes < tree.bytes {
+                tree.problems.push(format!(
+                    "{} bytes are needed but only {} are free on the destination",
+                    tree.bytes, available.bytes
+                ));
+            }
+            if let Some(inodes) = available.inodes.filter(|&inodes| inodes < tree.inodes) {
+                tree.problems.push(format!(
+                    "{} inodes are needed but only {} are free on the destination",
+                    tree.inodes, inodes
+                ));
+            }
+        }
+    }
+    if tree.problems.is_empty() {
+        return Ok(());
+    }
+    if tree.problems.len() > MAX_PROBLEMS {
+        let more = tree.problems.len() - MAX_PROBLEMS;
+        tree.problems.truncate(MAX_PROBLEMS);
+        tree.problems.push(format!("{} more", more));
+    }
+    Err(ImplinkError::PreflightFailed {
+        src: src.to_path_buf(),
+        dst: dst.to_path_buf(),
+        problems: tree.problems,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, set_permissions, write, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    /// Permissions don't stop root, the checks can't fail then.
+    fn is_root() -> bool {
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    fn problems(src: &Path, dst: &Path, ignore: &Ignore) -> Vec<String> {
+        match check(src, dst, ignore) {
+            Ok(()) => Vec::new(),
+            Err(ImplinkError::PreflightFailed { problems, .. }) => problems,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn passes_movable_trees() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.path().join("src");
+        create_dir_all(src.join("sub")).unwrap();
+        write(src.join("sub/a"), "a").unwrap();
+        assert!(problems(&src, &tmp.path().join("new/dst"), &Ignore::default()).is_empty());
+    }
+
+    #[test]
+    fn checks_only_the_directories_of_a_rename() {
+        if is_root() {
+            return;
+        }
+        let tmp = tempdir().unwrap();
+        let src = tmp.path().join("parent/src");
+        create_dir_all(&src).unwrap();
+        write(src.join("locked"), "").unwrap();
+        set_permissions(src.join("locked"), Permissions::from_mode(0o000)).unwrap();
+        let dst = tmp.path().join("parent/dst");
+        assert!(problems(&src, &dst, &Ignore::default()).is_empty());
+
+        set_permissions(tmp.path().join("parent"), Permissions::from_mode(0o555)).unwrap();
+        let found = problems(&src, &dst, &Ignore::default());
+        set_permissions(tmp.path().join("parent"), Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert!(found[0].contains("can't be removed from"));
+        assert!(found[1].contains("can't be written to"));
+    }
+
+    #[test]
+    fn walks_trees_with_ignored_entries() {
+        if is_root() {
+            return;
+        }
+        let tmp = tempdir().unwrap();
+        let src = tmp.path().join("src");
+        create_dir_all(&src).unwrap();
+        for name in ["locked", "secret"] {
+            write(src.join(name), "").unwrap();
+            set_permissions(src.join(name), Permissions::from_mode(0o000)).unwrap();
+        }
+        let ignore = Ignore::new(&src, &["secret".to_string()]).unwrap();
+        let found = problems(&src, &tmp.path().join("dst"), &ignore);
+        assert_eq!(
+            found,
+            [format!("'{}' can't be read", src.join("locked").display())]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use crate::error::ImplinkError;
+use crate::mapping::Mapping;
+
+/// What happened to a single mapping entry during a restore or unlink.
+#[derive(Debug)]
+pub enum EntryStatus {
+    /// The link has been created
+    Linked,
+    /// The link has been removed
+    Unlinked,
+    /// The destination already was a link to the source, nothing was changed
+    AlreadyCorrect,
+    /// The entry was not attempted, with the reason why
+    Skipped(String),
+    /// Creating the link failed
+    Failed(ImplinkError),
+}
+
+/// The result of restoring or unlinking a single mapping entry.
+#[derive(Debug)]
+pub struct EntryReport {
+    pub mapping: Mapping,
+    pub status: EntryStatus,
+}
+
+/// Per-entry results of restoring or unlinking a mapping file.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub entries: Vec<EntryReport>,
+}
+
+impl Report {
+    /// Number of entries with the given status kind
+    fn count(&self, predicate: impl Fn(&EntryStatus) -> bool) -> usize {
+        self.entries.iter().filter(|e| predicate(&e.status)).count()
+    }
+
+    pub fn linked(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::Linked))
+    }
+
+    pub fn unlinked(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::Unlinked))
+    }
+
+    pub fn already_correct(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::AlreadyCorrect))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::Skipped(_)))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::Failed(_)))
+    }
+
+    /// The first entry which failed, if any
+    pub fn first_error(&self) -> Option<&ImplinkError> {
+        self.entries.iter().find_map(|e| match &e.status {
+            EntryStatus::Failed(e) => Some(e),
+            _ => None,
+        })
+    }
+}
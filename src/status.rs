@@ -0,0 +1,108 @@
+use crate::error::Result;
+use crate::ignores::Ignore;
+use crate::mapping::{LinkKind, Mapping, MappingFile};
+use crate::mover::is_hard_linked;
+use crate::ops::read_link_absolute;
+use crate::paths::normalize;
+use std::fs::symlink_metadata;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// How a mapping entry compares to the filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkState {
+    /// The destination is a link to the source, which exists
+    Ok,
+    /// Nothing exists at the destination
+    MissingLink,
+    /// The destination is a link to somewhere else
+    PointsElsewhere(PathBuf),
+    /// The destination is a link to the source, but the source doesn't exist
+    DanglingSource,
+    /// The destination is a real file or directory instead of a link, or no longer
+    /// shares its files with the source for hard links
+    Replaced { is_dir: bool },
+    /// The glob `src` of the entry matches nothing
+    NoMatches,
+    /// The entry or the destination couldn't be read, with the reason why
+    Unreadable(String),
+}
+
+impl LinkState {
+    pub fn is_ok(&self) -> bool {
+        *self == LinkState::Ok
+    }
+}
+
+/// The state of a single mapping entry.
+#[derive(Debug, Clone)]
+pub struct EntryState {
+    pub mapping: Mapping,
+    pub state: LinkState,
+}
+
+fn check_entry(mapping: &Mapping, base: &Path) -> LinkState {
+    let (src, dst) = match mapping.resolve(base) {
+        Ok(paths) => paths,
+        Err(e) => return LinkState::Unreadable(e.to_string()),
+    };
+    let metadata = match symlink_metadata(&dst) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return LinkState::MissingLink,
+        Err(e) => return LinkState::Unreadable(e.to_string()),
+    };
+    if mapping.kind == LinkKind::Hard {
+        return match src.try_exists() {
+            Ok(false) => LinkState::DanglingSource,
+            // Only the ignore files in the source are known here, not extra patterns
+            Ok(true) if is_hard_linked(&src, &dst, &Ignore::new(&src, &[]).unwrap_or_default()) => {
+                LinkState::Ok
+            }
+            Ok(true) => LinkState::Replaced {
+                is_dir: metadata.is_dir(),
+            },
+            Err(e) => LinkState::Unreadable(e.to_string()),
+        };
+    }
+    if !metadata.file_type().is_symlink() {
+        return LinkState::Replaced {
+            is_dir: metadata.is_dir(),
+        };
+    }
+    match read_link_absolute(&dst) {
+        Ok(target) if target != normalize(&src) => LinkState::PointsElsewhere(target),
+        Ok(_) => match src.try_exists() {
+            Ok(true) => LinkState::Ok,
+            Ok(false) => LinkState::DanglingSource,
+            Err(e) => LinkState::Unreadable(e.to_string()),
+        },
+        Err(e) => LinkState::Unreadable(e.to_string()),
+    }
+}
+
+/// Compares every entry of a mapping file to the filesystem without changing anything.
+///
+/// Glob entries give one state per link they expand to.
+pub fn status(file: impl AsRef<Path>) -> Result<Vec<EntryState>> {
+    let mapping_file = MappingFile::load(file.as_ref())?;
+    let base = MappingFile::base_dir(file.as_ref())?;
+    let mut entries = Vec::new();
+    for mapping in mapping_file.mapping {
+        // Glob entries are checked link by link
+        match mapping.expand(&base) {
+            Ok(expanded) if expanded.is_empty() => entries.push(EntryState {
+                state: LinkState::NoMatches,
+                mapping,
+            }),
+            Ok(expanded) => entries.extend(expanded.into_iter().map(|mapping| EntryState {
+                state: check_entry(&mapping, &base),
+                mapping,
+            })),
+            Err(e) => entries.push(EntryState {
+                state: LinkState::Unreadable(e.to_string()),
+                mapping,
+            }),
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,287 @@
+use crate::error::{ImplinkError, Result};
+use crate::ignores::{entries, is_ignore_file, Ignore};
+use crate::mapping::LinkKind;
+use crate::mover::is_hard_linked;
+use crate::ops::read_link_absolute;
+use crate::paths::normalize;
+use std::collections::HashMap;
+use std::fs::{read_link, symlink_metadata};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A change needed to stow a package, planned before anything is touched.
+#[derive(Debug)]
+pub(crate) enum Step {
+    /// Link `dst` to `src`, replacing whatever is there with `force`
+    Link { src: PathBuf, dst: PathBuf },
+    /// `dst` already is a link to `src`
+    Linked { src: PathBuf, dst: PathBuf },
+    /// Create the directory `dst` to link the contents of a directory into, because some of
+    /// them are ignored
+    CreateDir { dst: PathBuf },
+    /// Replace the link at `dst` to the directory `target` of another package with a real
+    /// directory holding links to everything in `target`
+    Unfold {
+        dst: PathBuf,
+        target: PathBuf,
+        relative: bool,
+    },
+}
+
+/// What a path in the target tree looks like once the steps planned so far are done.
+enum Existing {
+    Missing,
+    Dir,
+    Link(PathBuf),
+    Other,
+}
+
+struct Planner {
+    kind: LinkKind,
+    force: bool,
+    /// Directory the packages are in, links into it belong to a package and may be unfolded
+    stow_dir: PathBuf,
+    /// Unfolded directories and the directory of the other package they used to link to
+    unfolded: HashMap<PathBuf, (PathBuf, bool)>,
+    steps: Vec<Step>,
+    conflicts: Vec<PathBuf>,
+}
+
+impl Planner {
+    fn existing(&self, path: &Path) -> Result<Existing> {
+        if let Some((target, _)) = path.parent().and_then(|parent| self.unfolded.get(parent)) {
+            // Will be a link into the other package once its parent is unfolded
+            let old = target.join(path.file_name().unwrap_or_default());
+            return Ok(match symlink_metadata(&old) {
+                Ok(_) => Existing::Link(old),
+                Err(_) => Existing::Missing,
+            });
+        }
+        let io_error = |e| ImplinkError::Io {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let metadata = match symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Existing::Missing),
+            Err(e) => return Err(io_error(e)),
+        };
+        Ok(if metadata.file_type().is_symlink() {
+            Existing::Link(read_link_absolute(path).map_err(io_error)?)
+        } else if metadata.is_dir() {
+            Existing::Dir
+        } else {
+            Existing::Other
+        })
+    }
+
+    /// Whether the link at `dst` is stored relative to its directory.
+    fn is_relative(&self, dst: &Path) -> bool {
+        match dst.parent().and_then(|parent| self.unfolded.get(parent)) {
+            Some((_, relative)) => *relative,
+            None => read_link(dst).is_ok_and(|target| target.is_relative()),
+        }
+    }
+
+    fn plan(&mut self, package: &Path, target: &Path, ignore: &Ignore) -> Result<()> {
+        let ignore = ignore.child(package)?;
+        for src in entries(package)? {
+            let dst = target.join(src.file_name().unwrap_or_default());
+            // Links inside the package are linked as they are, not followed
+            let src_is_dir = symlink_metadata(&src).is_ok_and(|m| m.is_dir());
+            if is_ignore_file(&src) || ignore.is_ignored(&src, src_is_dir) {
+                continue;
+            }
+            match self.existing(&dst)? {
+                // Linking the whole directory would bring its ignored entries along
+                Existing::Missing if src_is_dir && ignore.ignores_any(&src)? => {
+                    self.steps.push(Step::CreateDir { dst: dst.clone() });
+                    self.plan(&src, &dst, &ignore)?;
+                }
+                Existing::Missing => self.steps.push(Step::Link { src, dst }),
+                Existing::Link(old) if old == normalize(&src) => {
+                    self.steps.push(Step::Linked { src, dst })
+                }
+                Existing::Link(old)
+                    if src_is_dir && old.starts_with(&self.stow_dir) && old.is_dir() =>
+                {
+                    let relative = self.is_relative(&dst);
+                    self.steps.push(Step::Unfold {
+                        dst: dst.clone(),
+                        target: old.clone(),
+                        relative,
+                    });
+                    self.unfolded.insert(dst.clone(), (old, relative));
+                    self.plan(&src, &dst, &ignore)?;
+                }
+                Existing::Dir if src_is_dir => self.plan(&src, &dst, &ignore)?,
+                Existing::Other
+                    if self.kind == LinkKind::Hard && is_hard_linked(&src, &dst, &ignore) =>
+                {
+                    self.steps.push(Step::Linked { src, dst })
+                }
+                _ if self.force => self.steps.push(Step::Link { src, dst }),
+                _ => self.conflicts.push(dst),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Plans linking every entry of `package` into `target` the way GNU Stow does.
+///
+/// Entries missing from `target` are linked as a whole, directories which already exist
+/// are descended into so their contents are linked one by one. A link to a directory of
+/// another package in the same parent directory is unfolded into a real directory first.
+/// Anything else in the way is a conflict, unless `force` replaces it. Ignored entries
+/// and ignore files are left out, directories with ignored entries are never linked as
+/// a whole.
+pub(crate) fn plan(
+    package: &Path,
+    target: &Path,
+    kind: LinkKind,
+    force: bool,
+    ignore: &Ignore,
+) -> Result<Vec<Step>> {
+    let mut planner = Planner {
+        kind,
+        force,
+        stow_dir: normalize(package.parent().unwrap_or(package)),
+        unfolded: HashMap::new(),
+        steps: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    planner.plan(package, target, ignore)?;
+    if !planner.conflicts.is_empty() {
+        return Err(ImplinkError::StowConflict {
+            package: package.to_path_buf(),
+            conflicts: planner.conflicts,
+        });
+    }
+    Ok(planner.steps)
+}
+
+/// Everything in a directory being unfolded, which gets linked into the new directory.
+pub(crate) fn unfolded_entries(target: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = entries(target)?;
+    entries.retain(|entry| !is_ignore_file(entry));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    fn stow_plan(package: &Path, target: &Path, force: bool) -> Result<Vec<Step>> {
+        plan(
+            package,
+            target,
+            LinkKind::Symlink,
+            force,
+            &Ignore::default(),
+        )
+    }
+
+    /// The destinations of the steps, in order.
+    fn destinations(steps: &[Step], target: &Path) -> Vec<String> {
+        steps
+            .iter()
+            .map(|step| {
+                let (kind, dst) = match step {
+                    Step::Link { dst, .. } => ("link", dst),
+                    Step::Linked { dst, .. } => ("linked", dst),
+                    Step::CreateDir { dst } => ("mkdir", dst),
+                    Step::Unfold { dst, .. } => ("unfold", dst),
+                };
+                let dst = dst.strip_prefix(target).unwrap().to_string_lossy();
+                format!("{} {}", kind, dst)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn links_missing_directories_as_a_whole() {
+        let tmp = tempdir().unwrap();
+        let (package, target) = (tmp.path().join("stow/pkg"), tmp.path().join("target"));
+        create_dir_all(package.join("bin")).unwrap();
+        create_dir_all(target.join("share")).unwrap();
+        create_dir_all(package.join("share/doc")).unwrap();
+        write(package.join("bin/tool"), "").unwrap();
+        write(package.join("share/doc/readme"), "").unwrap();
+
+        let steps = stow_plan(&package, &target, false).unwrap();
+        let mut planned = destinations(&steps, &target);
+        planned.sort();
+        assert_eq!(planned, ["link bin", "link share/doc"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unfolds_links_to_other_packages() {
+        let tmp = tempdir().unwrap();
+        let stow = tmp.path().join("stow");
+        let target = tmp.path().join("target");
+        create_dir_all(stow.join("a/share")).unwrap();
+        create_dir_all(stow.join("b/share")).unwrap();
+        create_dir_all(&target).unwrap();
+        write(stow.join("a/share/x"), "").unwrap();
+        write(stow.join("b/share/y"), "").unwrap();
+        std::os::unix::fs::symlink(stow.join("a/share"), target.join("share")).unwrap();
+
+        let steps = stow_plan(&stow.join("b"), &target, false).unwrap();
+        assert_eq!(
+            destinations(&steps, &target),
+            ["unfold share", "link share/y"]
+        );
+        assert!(matches!(
+            &steps[0],
+            Step::Unfold { target: old, relative: false, .. } if *old == stow.join("a/share")
+        ));
+
+        // Already stowed packages are left as they are
+        let steps = stow_plan(&stow.join("a"), &target, false).unwrap();
+        assert_eq!(destinations(&steps, &target), ["linked share"]);
+    }
+
+    #[test]
+    fn reports_every_conflict_unless_forced() {
+        let tmp = tempdir().unwrap();
+        let (package, target) = (tmp.path().join("stow/pkg"), tmp.path().join("target"));
+        create_dir_all(package.join("dir")).unwrap();
+        create_dir_all(&target).unwrap();
+        write(package.join("a"), "").unwrap();
+        write(package.join("b"), "").unwrap();
+        write(target.join("a"), "").unwrap();
+        write(target.join("dir"), "").unwrap();
+
+        match stow_plan(&package, &target, false) {
+            Err(ImplinkError::StowConflict { mut conflicts, .. }) => {
+                conflicts.sort();
+                assert_eq!(conflicts, [target.join("a"), target.join("dir")]);
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        let steps = stow_plan(&package, &target, true).unwrap();
+        assert_eq!(steps.len(), 3);
+    }
+
+    #[test]
+    fn creates_directories_with_ignored_entries() {
+        let tmp = tempdir().unwrap();
+        let (package, target) = (tmp.path().join("stow/pkg"), tmp.path().join("target"));
+        create_dir_all(package.join("config")).unwrap();
+        create_dir_all(&target).unwrap();
+        write(package.join("config/settings"), "").unwrap();
+        write(package.join("config/secret"), "").unwrap();
+        write(package.join(".implinkignore"), "secret\n").unwrap();
+
+        let ignore = Ignore::new(&package, &[]).unwrap();
+        let steps = plan(&package, &target, LinkKind::Symlink, false, &ignore).unwrap();
+        assert_eq!(
+            destinations(&steps, &target),
+            ["mkdir config", "link config/settings"]
+        );
+    }
+}